@@ -0,0 +1,121 @@
+use serde::Deserialize;
+
+use crate::{error::GittyError, http_agent::HttpProxyAgent, repository::GitRepository};
+
+const GLOB_CHARACTERS: [char; 2] = ['*', '?'];
+
+#[derive(Deserialize)]
+struct MatchingRef {
+    #[serde(rename = "ref")]
+    name: String,
+}
+
+pub fn is_branch_pattern(pattern: &str)-> bool {
+    pattern.contains(GLOB_CHARACTERS)
+}
+
+/// Glob match on branch names: `?` is any one character and `*` any run of characters within one
+/// `/` separated segment, so `release/*` takes `release/1.2` but not `release/1.2/rc`. `**` crosses segments.
+pub fn matches_branch_pattern(pattern: &str, branch: &str)-> bool {
+    match pattern.chars().next() {
+        None => branch.is_empty(),
+        Some('*') if pattern.starts_with("**") => {
+            let rest = &pattern[2..];
+            branch.char_indices().map(|(index, _)| index).chain(std::iter::once(branch.len()))
+                .any(|index| matches_branch_pattern(rest, &branch[index..]))
+        },
+        Some('*') => {
+            let rest = &pattern[1..];
+            let segment_length = branch.find('/').unwrap_or(branch.len());
+            branch[..segment_length].char_indices().map(|(index, _)| index).chain(std::iter::once(segment_length))
+                .any(|index| matches_branch_pattern(rest, &branch[index..]))
+        },
+        Some(expected) => match branch.chars().next() {
+            Some(character) if character == expected || (expected == '?' && character != '/') =>
+                matches_branch_pattern(&pattern[expected.len_utf8()..], &branch[character.len_utf8()..]),
+            _ => false,
+        },
+    }
+}
+
+impl GitRepository {
+    //https://docs.github.com/en/rest/git/refs?apiVersion=2022-11-28#list-matching-references
+    /// Branches whose names start with `prefix`.
+    fn get_branches_starting_with(&self, http_agent: &HttpProxyAgent, prefix: &str)-> Result<Vec<String>, GittyError> {
+        let url = format!("{}/repos/{}/{}/git/matching-refs/heads/{}", self.get_base_rest_url(), self.get_org_name(), self.get_repository_name(), prefix);
+        let response = http_agent.get(&url)
+            .set("Authorization",&self.get_bearer_token_string()?)
+            .set("X-GitHub-Api-Version", "2022-11-28")
+            .conditional()
+            .call()?;
+        let string_response = response.into_string()
+            .map_err(|error| GittyError::Network(error.to_string()))?;
+        Ok(serde_json::from_str::<Vec<MatchingRef>>(&string_response)?.into_iter()
+            .filter_map(|matching_ref| matching_ref.name.strip_prefix("refs/heads/").map(str::to_string))
+            .collect())
+    }
+
+    /// The main branch followed by every branch matching one of `patterns`, each once and in pattern order.
+    /// Plain names are taken as they are; only patterns with glob characters are looked up.
+    pub fn expand_branch_patterns(&self, http_agent: &HttpProxyAgent, patterns: &[String])-> Result<Vec<String>, GittyError> {
+        let mut branches = vec![self.get_main_branch_name().to_string()];
+        for pattern in patterns {
+            let mut matches = if is_branch_pattern(pattern) {
+                let prefix = &pattern[..pattern.find(GLOB_CHARACTERS).unwrap_or(pattern.len())];
+                let mut matches: Vec<String> = self.get_branches_starting_with(http_agent, prefix)?.into_iter()
+                    .filter(|branch| matches_branch_pattern(pattern, branch))
+                    .collect();
+                matches.sort();
+                matches
+            } else {
+                vec![pattern.clone()]
+            };
+            matches.retain(|branch| !branches.contains(branch));
+            branches.extend(matches);
+        }
+        Ok(branches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{http_agent::testing::serve_responses, remote::RemoteLocation};
+
+    #[test]
+    fn can_match_branch_patterns() {
+        let cases = vec![
+            ("release/*", "release/1.2", true),
+            ("release/*", "release/1.2/rc", false),
+            ("release/*", "release/", true),
+            ("release/*", "releases/1.2", false),
+            ("release/**", "release/1.2/rc", true),
+            ("hotfix/*-urgent", "hotfix/login-urgent", true),
+            ("hotfix/*-urgent", "hotfix/login", false),
+            ("v?.x", "v2.x", true),
+            ("v?.x", "v10.x", false),
+            ("main", "main", true),
+            ("main", "maintenance", false),
+        ];
+        for (pattern, branch, expected) in cases {
+            assert_eq!(expected, matches_branch_pattern(pattern, branch), "{} against {}", pattern, branch);
+        }
+    }
+
+    #[test]
+    fn expands_patterns_through_the_refs_api() {
+        let (base_url, server) = serve_responses(vec![
+            r#"HTTP/1.1 200 OK
+
+[{"ref":"refs/heads/release/1.2"},{"ref":"refs/heads/release/1.10/rc"},{"ref":"refs/heads/release/1.1"}]"#.replace('\n', "\r\n"),
+        ]);
+        let http_agent = HttpProxyAgent::new_with_proxy("");
+        let repository = GitRepository::new("token".to_string(), RemoteLocation::parse(&format!("{}/bfrazho/gitty", base_url)).unwrap(), "main".to_string());
+
+        assert_eq!(
+            vec!["main", "release/1.1", "release/1.2", "develop"],
+            repository.expand_branch_patterns(&http_agent, &["main".to_string(), "release/*".to_string(), "develop".to_string()]).unwrap()
+        );
+        assert!(server.join().unwrap()[0].starts_with("GET /api/v3/repos/bfrazho/gitty/git/matching-refs/heads/release/ "));
+    }
+}
@@ -0,0 +1,199 @@
+use std::{io, path::PathBuf};
+
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+
+use crate::error::GittyError;
+
+/// Approves commits pushed by the people you are pairing with.
+#[derive(Parser, Debug)]
+#[command(name = "gitty", version, about)]
+pub struct Cli {
+    /// Path inside the git repository to watch
+    #[arg(long, global = true, default_value = ".")]
+    pub repo: PathBuf,
+
+    /// Remote to read the GitHub repository from, defaults to the fetch remote
+    #[arg(long, global = true)]
+    pub remote: Option<String>,
+
+    /// When the repository is a fork, watch it as well as its upstream
+    #[arg(long, global = true)]
+    pub watch_fork_and_upstream: bool,
+
+    /// Branch to watch, defaults to the one the remote HEAD or GitHub names as default
+    #[arg(long, global = true)]
+    pub branch: Option<String>,
+
+    /// Seconds to wait between polls
+    #[arg(long, global = true, value_name = "SECONDS")]
+    pub interval: Option<u64>,
+
+    /// Credential profile to use instead of the one matching the repository host
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+
+    /// Append every HTTP request and response to this file, with credentials redacted
+    #[arg(long, global = true, value_name = "FILE")]
+    pub trace_http: Option<PathBuf>,
+
+    /// Include request and response bodies in the HTTP trace
+    #[arg(long, global = true, requires = "trace_http")]
+    pub trace_http_bodies: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Select collaborators and approve their commits as they land (the default)
+    Watch,
+    /// Approve a single commit or every commit in a `base..head` range
+    Approve {
+        /// A commit sha, or a range such as `abc123..def456`
+        target: String,
+    },
+    /// Show what the watcher has seen for this repository
+    Status,
+    /// Watch several repositories at once with one token and one collaborator selection
+    Workspace {
+        /// Repositories as owner/repo, defaults to `workspace_repositories`
+        repositories: Vec<String>,
+        /// Also watch every git checkout directly inside this directory, defaults to `workspace_dir`
+        #[arg(long, value_name = "DIR")]
+        dir: Option<PathBuf>,
+    },
+    /// List the remotes and the GitHub repository each points at
+    Remotes,
+    /// List the collaborators that can be selected
+    Collaborators {
+        /// Minimum permission: pull, triage, push, maintain or admin
+        #[arg(long)]
+        permission: Option<String>,
+    },
+    /// Show the settings gitty would run with
+    Config,
+    /// Log in through the browser with the OAuth device flow and store the token
+    Login,
+    /// Manage how GitHub is reached through proxies
+    Proxy {
+        #[command(subcommand)]
+        action: ProxyCommand,
+    },
+    /// Manage the stored GitHub token
+    Token {
+        #[command(subcommand)]
+        action: TokenCommand,
+    },
+    /// List every commit gitty has approved
+    Audit,
+    /// Print shell completions to stdout
+    Completions {
+        shell: Shell,
+    },
+    /// Print a man page to stdout
+    Man,
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum ProxyCommand {
+    /// Prompt for a proxy to fall back to when GitHub cannot be reached directly and remember it
+    Set,
+    /// Store the username and password for an authenticated proxy
+    Login {
+        /// The proxy url, e.g. `http://proxy.corp:8080`
+        proxy: String,
+    },
+    /// Show which proxy the repository's host is reached through
+    Show,
+}
+
+#[derive(Subcommand, Debug, PartialEq, Eq)]
+pub enum TokenCommand {
+    /// Prompt for a new token and store it
+    Set,
+    /// Show where the token is stored
+    Show,
+    /// Move a plaintext token into the encrypted credential store
+    Migrate,
+}
+
+impl Cli {
+    pub fn get_command(&self)-> &Command {
+        self.command.as_ref().unwrap_or(&Command::Watch)
+    }
+}
+
+pub fn print_completions(shell: Shell) {
+    clap_complete::generate(shell, &mut Cli::command(), "gitty", &mut io::stdout());
+}
+
+pub fn print_man_page()-> Result<(), GittyError> {
+    clap_mangen::Man::new(Cli::command())
+        .render(&mut io::stdout())
+        .map_err(|error| GittyError::Config(format!("failed to render man page: {}", error)))
+}
+
+/// Splits `base..head` into its ends, or returns `None` for a single commit.
+pub fn parse_commit_range(target: &str)-> Option<(&str, &str)> {
+    target.split_once("...").or_else(|| target.split_once(".."))
+}
+
+#[cfg(test)]
+mod test {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn cli_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn watch_is_the_default_command() {
+        let cli = Cli::parse_from(["gitty"]);
+        assert_eq!(&Command::Watch, cli.get_command());
+        assert_eq!(PathBuf::from("."), cli.repo);
+    }
+
+    #[test]
+    fn global_flags_work_after_subcommand() {
+        let cli = Cli::parse_from(["gitty", "status", "--branch", "develop", "--interval", "60", "--remote", "upstream"]);
+        assert_eq!(&Command::Status, cli.get_command());
+        assert_eq!(Some("develop".to_string()), cli.branch);
+        assert_eq!(Some(60), cli.interval);
+        assert_eq!(Some("upstream".to_string()), cli.remote);
+    }
+
+    #[test]
+    fn can_watch_fork_and_upstream() {
+        let cli = Cli::parse_from(["gitty", "--watch-fork-and-upstream"]);
+        assert!(cli.watch_fork_and_upstream);
+        assert_eq!(&Command::Watch, cli.get_command());
+        assert!(!Cli::parse_from(["gitty", "remotes"]).watch_fork_and_upstream);
+    }
+
+    #[test]
+    fn can_parse_workspace() {
+        let cli = Cli::parse_from(["gitty", "workspace", "team/api", "team/web", "--dir", "checkouts"]);
+        assert_eq!(
+            &Command::Workspace { repositories: vec!["team/api".to_string(), "team/web".to_string()], dir: Some(PathBuf::from("checkouts")) },
+            cli.get_command()
+        );
+    }
+
+    #[test]
+    fn can_parse_approve_target() {
+        let cli = Cli::parse_from(["gitty", "approve", "abc..def"]);
+        assert_eq!(&Command::Approve { target: "abc..def".to_string() }, cli.get_command());
+    }
+
+    #[test]
+    fn can_split_commit_ranges() {
+        assert_eq!(Some(("abc", "def")), parse_commit_range("abc..def"));
+        assert_eq!(Some(("abc", "def")), parse_commit_range("abc...def"));
+        assert_eq!(None, parse_commit_range("abc"));
+    }
+}
@@ -1,8 +1,10 @@
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
+use chrono::{DateTime, Utc};
 use serde::{Serialize, Deserialize};
+use ureq::Response;
 
-use crate::{user_input_generator::MultiSelectGeneratorTrait, repository::GitRepository, http_agent::HttpProxyAgent};
+use crate::{user_input_generator::MultiSelectGeneratorTrait, repository::GitRepository, http_agent::{HttpProxyAgent, HttpRequest}, error::GittyError};
 
 
 #[derive(PartialEq, Eq, Serialize, Deserialize, Debug, Clone, PartialOrd, Ord)]
@@ -29,6 +31,58 @@ impl Display for Collaborator{
     }
 }
 
+/// Minimum permission a collaborator needs on the repository to be offered in the picker.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum CollaboratorPermission {
+    Pull,
+    Triage,
+    Push,
+    Maintain,
+    #[default]
+    Admin,
+}
+
+impl CollaboratorPermission {
+    pub fn as_str(&self)-> &'static str {
+        match self {
+            CollaboratorPermission::Pull => "pull",
+            CollaboratorPermission::Triage => "triage",
+            CollaboratorPermission::Push => "push",
+            CollaboratorPermission::Maintain => "maintain",
+            CollaboratorPermission::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for CollaboratorPermission {
+    type Err = GittyError;
+
+    fn from_str(permission: &str) -> Result<Self, Self::Err> {
+        match permission.trim().to_lowercase().as_str() {
+            "pull" => Ok(CollaboratorPermission::Pull),
+            "triage" => Ok(CollaboratorPermission::Triage),
+            "push" => Ok(CollaboratorPermission::Push),
+            "maintain" => Ok(CollaboratorPermission::Maintain),
+            "admin" => Ok(CollaboratorPermission::Admin),
+            other => Err(GittyError::Config(format!("unknown collaborator permission \"{}\", expected one of pull, triage, push, maintain or admin", other))),
+        }
+    }
+}
+
+impl Display for CollaboratorPermission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Pulls the `rel="next"` target out of a REST `Link` header, if there is another page.
+pub fn get_next_page_url(link_header: Option<&str>)-> Option<String> {
+    link_header?.split(',')
+        .map(|link| link.split(';').map(str::trim).collect::<Vec<&str>>())
+        .find(|parts| parts.iter().skip(1).any(|parameter| parameter == &"rel=\"next\""))
+        .and_then(|parts| parts.first().map(|url| url.trim_start_matches('<').trim_end_matches('>').to_string()))
+}
+
 impl GitRepository {
     fn build_get_collaborators_query(&self)-> String{
         format!(r#"
@@ -37,42 +91,76 @@ impl GitRepository {
         
     }
 
-    //https://docs.github.com/en/rest/collaborators/collaborators?apiVersion=2022-11-28
-    pub fn get_collaborators(&self, http_agent: &HttpProxyAgent) -> Vec<Collaborator> {
-        let collaborator_query = self.build_get_collaborators_query();
-
-        let mut collaborators = match http_agent.get(&collaborator_query)
-        .set("Authorization",&self.get_bearer_token_string())
-        .set("X-GitHub-Api-Version", "2022-11-28")
-        .query("permission", "admin")
-        .query("per_page", "100")
-        .call()
-        {
-            Ok(response) => {
-                let string_response = &response.into_string().unwrap();
-                serde_json::from_str::<Vec<Collaborator>>(string_response)
-                .expect("failed to deserialize")
-            },
-            Err(error) => panic!("{}", error),
-        };
+    fn build_collaborators_request(&self, http_agent: &HttpProxyAgent, url: &str)-> Result<HttpRequest, GittyError> {
+        Ok(http_agent.get(url)
+            .set("Authorization",&self.get_bearer_token_string()?)
+            .set("X-GitHub-Api-Version", "2022-11-28")
+            .conditional())
+    }
+
+    /// Follows the `Link` header through every page, getting each one from `fetch`.
+    /// Gives `None` as soon as `fetch` has no response for a page.
+    fn collect_collaborators(
+        &self,
+        http_agent: &HttpProxyAgent,
+        permission: CollaboratorPermission,
+        mut fetch: impl FnMut(HttpRequest)-> Result<Option<Response>, GittyError>,
+    )-> Result<Option<Vec<Collaborator>>, GittyError> {
+        let mut collaborators = Vec::new();
+
+        let mut request = self.build_collaborators_request(http_agent, &self.build_get_collaborators_query())?
+            .query("permission", permission.as_str())
+            .query("per_page", "100");
+        loop {
+            let Some(response) = fetch(request)? else {
+                return Ok(None)
+            };
+            let next_page_url = get_next_page_url(response.header("Link"));
+            let string_response = response.into_string()
+                .map_err(|error| GittyError::Network(error.to_string()))?;
+            collaborators.extend(serde_json::from_str::<Vec<Collaborator>>(&string_response)?);
+            match next_page_url {
+                Some(next_page_url) => request = self.build_collaborators_request(http_agent, &next_page_url)?,
+                None => break,
+            }
+        }
         collaborators.sort();
-        collaborators
+        Ok(Some(collaborators))
+    }
+
+    //https://docs.github.com/en/rest/collaborators/collaborators?apiVersion=2022-11-28
+    pub fn get_collaborators(&self, http_agent: &HttpProxyAgent, permission: CollaboratorPermission) -> Result<Vec<Collaborator>, GittyError> {
+        self.collect_collaborators(http_agent, permission, |mut request| Ok(Some(request.call()?)))
+            .map(Option::unwrap_or_default)
+    }
+
+    /// The collaborators as of the last fetch, and when that was, if every page is still in the cache.
+    pub fn get_cached_collaborators(&self, http_agent: &HttpProxyAgent, permission: CollaboratorPermission)-> Option<(Vec<Collaborator>, DateTime<Utc>)> {
+        let mut oldest_stored_at: Option<DateTime<Utc>> = None;
+        let collaborators = self.collect_collaborators(http_agent, permission, |request| {
+            let Some(cached) = request.cached() else {
+                return Ok(None)
+            };
+            oldest_stored_at = Some(oldest_stored_at.map_or(cached.get_stored_at(), |stored_at| stored_at.min(cached.get_stored_at())));
+            Ok(Some(cached.to_response()?))
+        }).ok()??;
+        Some((collaborators, oldest_stored_at?))
     }
 }
 
 
 
-pub fn ask_who_they_are_working_with(user_input_generator: &mut dyn MultiSelectGeneratorTrait<Collaborator>, collaborators: Vec<Collaborator>)-> Vec<Collaborator> {
+pub fn ask_who_they_are_working_with(user_input_generator: &mut dyn MultiSelectGeneratorTrait<Collaborator>, collaborators: Vec<Collaborator>)-> Result<Vec<Collaborator>, GittyError> {
 
     user_input_generator.get_multiselect_input(
         "Select your fellow collaborators", 
         collaborators
-    ).unwrap()
+    ).ok_or_else(|| GittyError::Config("no collaborators were selected".to_string()))
 }
 
 #[cfg(test)]
 mod test{
-    use gix::Url;
+    use crate::remote::RemoteLocation;
     use dotenv::dotenv;
     use std::env;
 
@@ -90,9 +178,33 @@ mod test{
                 node_id: "MDQ6VXNlcjMxMzkxNTc5".to_string(),
                 login: "bfrazho".to_string()
             }],
-            GitRepository::new(github_token, Url::try_from("git@github.com:bfrazho/gitty.git").unwrap(), "".to_string()).get_collaborators(&http_agent)
+            GitRepository::new(github_token, RemoteLocation::parse("git@github.com:bfrazho/gitty.git").unwrap(), "".to_string()).get_collaborators(&http_agent, CollaboratorPermission::Admin).unwrap()
         )
     }
+
+    #[test]
+    fn can_find_next_page_in_link_header() {
+        let link_header = r#"<https://api.github.com/repositories/1/collaborators?per_page=100&page=2>; rel="next", <https://api.github.com/repositories/1/collaborators?per_page=100&page=3>; rel="last""#;
+        assert_eq!(
+            Some("https://api.github.com/repositories/1/collaborators?per_page=100&page=2".to_string()),
+            get_next_page_url(Some(link_header))
+        );
+    }
+
+    #[test]
+    fn no_next_page_on_last_page() {
+        let link_header = r#"<https://api.github.com/repositories/1/collaborators?per_page=100&page=1>; rel="prev", <https://api.github.com/repositories/1/collaborators?per_page=100&page=1>; rel="first""#;
+        assert_eq!(None, get_next_page_url(Some(link_header)));
+        assert_eq!(None, get_next_page_url(None));
+    }
+
+    #[test]
+    fn can_parse_collaborator_permission() {
+        assert_eq!(Ok(CollaboratorPermission::Push), "push".parse::<CollaboratorPermission>());
+        assert_eq!(Ok(CollaboratorPermission::Maintain), " Maintain ".parse::<CollaboratorPermission>());
+        assert!("owner".parse::<CollaboratorPermission>().is_err());
+        assert_eq!(CollaboratorPermission::Admin, CollaboratorPermission::default());
+    }
  
     #[test]
     fn user_can_select_who_they_are_pairing_with() {
@@ -106,6 +218,6 @@ mod test{
         ];
         
         assert_eq!(vec![Collaborator::new("id 1".to_string(), "User 1".to_string())], 
-            ask_who_they_are_working_with(&mut user_input_generator, collaborators));
+            ask_who_they_are_working_with(&mut user_input_generator, collaborators).unwrap());
     }
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
 use chrono::{DateTime, Local};
 use serde::{Serialize, Deserialize};
 
-use crate::{repository::GitRepository, collaborator::Collaborator, http_agent::HttpProxyAgent};
+use crate::{repository::GitRepository, collaborator::{get_next_page_url, Collaborator}, http_agent::{HttpProxyAgent, HttpRequest}, error::GittyError, retry::is_retryable};
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize,Clone)]
 pub struct User {
@@ -31,9 +31,12 @@ impl Commit{
             None=> None
         }
     }
-    fn get_id(&self)->&str{
+    pub fn get_id(&self)->&str{
         &self.oid
     }
+    pub fn is_authored_by_any_of(&self, collaborators: &[Collaborator])-> bool {
+        self.get_author_id().is_some_and(|author_id| collaborators.iter().any(|collaborator| collaborator.get_id() == author_id))
+    }
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
@@ -46,7 +49,7 @@ struct Data {
 }
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
 struct ThisRepository {
-    object: ThisObject
+    object: Option<ThisObject>
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
@@ -55,27 +58,64 @@ struct ThisObject {
 }
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageInfo {
+    has_next_page: bool,
+    end_cursor: Option<String>
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct History {
-    nodes: Vec<Commit>
+    nodes: Vec<Commit>,
+    page_info: PageInfo
+}
+
+/// Why a walk back through a branch's history stopped.
+#[derive(PartialEq, Eq, Debug)]
+enum HistoryEnd {
+    ReachedStopOid,
+    Exhausted,
+    PageLimit,
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct ComparedCommit {
+    sha: String
 }
 
-fn filter_any_commits_that_do_not_match_collaborators(commits: Vec<Commit>, collaborators: &Vec<Collaborator>)->Vec<Commit>{
-    let collaborator_ids: Vec<&str> = collaborators.iter().map(|collaborator| collaborator.get_id()).collect();
-    commits.into_iter()
-        .filter(|commit| commit.get_author_id().is_some() && collaborator_ids.contains(&commit.get_author_id().as_ref().unwrap().as_str()))
-        .collect()
-} 
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct CommitComment {
+    body: String
+}
 
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct Comparison {
+    total_commits: usize,
+    commits: Vec<ComparedCommit>
+}
 
+/// Escapes `value` for a GraphQL string literal inside the JSON body the query is sent in.
+fn escape_graphql_string(value: &str)-> String {
+    let graphql_string = value.replace('\\', "\\\\").replace('"', "\\\"");
+    graphql_string.replace('\\', "\\\\").replace('"', "\\\"")
+}
 
 impl GitRepository{
-    fn build_get_commits_after_timestamp_query(&self, timestamp: DateTime<Local>)-> String{
+    fn build_history_query(&self, branch: &str, first: usize, since: Option<DateTime<Local>>, cursor: Option<&str>)-> String{
+        let mut history_arguments = format!("first: {}", first);
+        if let Some(since) = since {
+            history_arguments.push_str(&format!(", since: \\\"{}\\\"", since.to_rfc3339()));
+        }
+        if let Some(cursor) = cursor {
+            history_arguments.push_str(&format!(", after: \\\"{}\\\"", cursor));
+        }
         format!(r#"
             {{"query": "query {{
                     repository(owner: \"{org}\", name:\"{repo}\") {{
-                        object(expression: \"{main_branch_name}\") {{
+                        object(expression: \"{branch}\") {{
                             ... on Commit {{
-                                history(first: 100, since: \"{timestamp}\") {{
+                                history({history_arguments}) {{
                                     nodes {{
                                         oid,
                                         message,
@@ -85,54 +125,210 @@ impl GitRepository{
                                             }}
                                         }},
                                     }}
+                                    pageInfo {{
+                                        hasNextPage,
+                                        endCursor
+                                    }}
                                 }}
                             }}
                         }}
                     }}
                 }}"
             }}
-        "#, org=self.get_org_name(), repo=self.get_repository_name(), main_branch_name=self.get_main_branch_name(), timestamp=timestamp.to_rfc3339()).replace("\n", "")
-        
+        "#, org=escape_graphql_string(&self.get_org_name()), repo=escape_graphql_string(&self.get_repository_name()), branch=escape_graphql_string(branch), history_arguments=history_arguments).replace("\n", "")
     }
 
-    pub fn get_commits_matching_collaborators_since_timestamp(&self, http_agent: &HttpProxyAgent, collaborators: &Vec<Collaborator>, timestamp: DateTime<Local>)-> Vec<Commit> {
-        let graphql_query = self.build_get_commits_after_timestamp_query(timestamp);
-        let bearer_token = self.get_bearer_token_string();
+    fn build_get_commits_after_timestamp_query(&self, branch: &str, timestamp: DateTime<Local>, cursor: Option<&str>)-> String{
+        self.build_history_query(branch, 100, Some(timestamp), cursor)
+    }
+
+    fn build_get_commits_from_head_query(&self, branch: &str, cursor: Option<&str>)-> String{
+        self.build_history_query(branch, 100, None, cursor)
+    }
+
+    fn build_get_head_query(&self, branch: &str)-> String{
+        self.build_history_query(branch, 1, None, None)
+    }
+
+    fn query_history(&self, http_agent: &HttpProxyAgent, branch: &str, graphql_query: &str)-> Result<History, GittyError> {
+        let bearer_token = self.get_bearer_token_string()?;
         let url = self.get_graphql_url();
 
-        let commits = match http_agent.post(&url)
+        let response = http_agent.post(&url)
             .set("Authorization",&bearer_token)
-            .send_string(&graphql_query)
-            {
-                Ok(response) =>  {
-                    let string_response = &response.into_string().unwrap();
-                    serde_json::from_str::<QueryResult>(string_response)
-                    .expect("failed to deserialize").data.repository.object.history.nodes
+            .idempotent(true)
+            .send_string(graphql_query)?;
+        let string_response = response.into_string()
+            .map_err(|error| GittyError::Network(error.to_string()))?;
+        match serde_json::from_str::<QueryResult>(&string_response) {
+            // GitHub answers a ref that does not exist with a null object rather than an error.
+            Ok(query_result) => query_result.data.repository.object
+                .map(|object| object.history)
+                .ok_or_else(|| GittyError::NotFound(format!("{} on {}", branch, url))),
+            Err(_) if string_response.contains("\"errors\"") => Err(GittyError::NotFound(format!("{} on {}: {}", branch, url, string_response))),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Follows `pageInfo.endCursor` until the history is exhausted, `stop_at_oid` is reached
+    /// or `max_history_pages` pages have been read, returning commits newest first and which of those it was.
+    fn get_paginated_history(&self, http_agent: &HttpProxyAgent, branch: &str, build_query: impl Fn(Option<&str>)-> String, stop_at_oid: Option<&str>)-> Result<(Vec<Commit>, HistoryEnd), GittyError> {
+        let mut commits = Vec::new();
+        let mut cursor: Option<String> = None;
+        for _ in 0..self.get_max_history_pages() {
+            let history = self.query_history(http_agent, branch, &build_query(cursor.as_deref()))?;
+            for commit in history.nodes {
+                if Some(commit.get_id()) == stop_at_oid {
+                    return Ok((commits, HistoryEnd::ReachedStopOid));
+                }
+                commits.push(commit);
+            }
+            match history.page_info {
+                PageInfo { has_next_page: true, end_cursor: Some(end_cursor) } => cursor = Some(end_cursor),
+                _ => return Ok((commits, HistoryEnd::Exhausted)),
+            }
+        }
+        Ok((commits, HistoryEnd::PageLimit))
+    }
+
+    pub fn get_branch_commits_since_timestamp(&self, http_agent: &HttpProxyAgent, branch: &str, timestamp: DateTime<Local>)-> Result<Vec<Commit>, GittyError> {
+        let (commits, end) = self.get_paginated_history(http_agent, branch, |cursor| self.build_get_commits_after_timestamp_query(branch, timestamp, cursor), None)?;
+        if end == HistoryEnd::PageLimit {
+            println!("Stopped reading history of {} after {} pages ({} commits), raise the page limit to read further", branch, self.get_max_history_pages(), commits.len());
+        }
+        Ok(commits)
+    }
+
+    /// Walks the branch from its head back to `last_seen_oid` (exclusive), newest first.
+    /// This follows what actually landed on the branch, regardless of when the commits were authored.
+    /// Gives `None` when the whole history was read without finding `last_seen_oid`, e.g. after a force-push, rather than every older commit.
+    /// Running out of pages first is an error, since skipping to the head would leave the unread commits unapproved.
+    pub fn get_branch_commits_since_oid(&self, http_agent: &HttpProxyAgent, branch: &str, last_seen_oid: &str)-> Result<Option<Vec<Commit>>, GittyError> {
+        match self.get_paginated_history(http_agent, branch, |cursor| self.build_get_commits_from_head_query(branch, cursor), Some(last_seen_oid))? {
+            (commits, HistoryEnd::ReachedStopOid) => Ok(Some(commits)),
+            (_, HistoryEnd::Exhausted) => Ok(None),
+            (commits, HistoryEnd::PageLimit) => Err(GittyError::Config(format!(
+                "read {} pages ({} commits) of {} without reaching {}, raise max_history_pages (GITTY_MAX_HISTORY_PAGES) to catch up",
+                self.get_max_history_pages(), commits.len(), branch, last_seen_oid))),
+        }
+    }
+
+    /// The commit at the tip of `branch`, or `None` when it has no commits.
+    pub fn get_branch_head_oid(&self, http_agent: &HttpProxyAgent, branch: &str)-> Result<Option<String>, GittyError> {
+        let history = self.query_history(http_agent, branch, &self.build_get_head_query(branch))?;
+        Ok(history.nodes.first().map(|commit| commit.get_id().to_string()))
+    }
+
+    fn build_compare_request(&self, http_agent: &HttpProxyAgent, url: &str)-> Result<HttpRequest, GittyError> {
+        Ok(http_agent.get(url)
+            .set("Authorization",&self.get_bearer_token_string()?)
+            .set("X-GitHub-Api-Version", "2022-11-28"))
+    }
+
+    //https://docs.github.com/en/rest/commits/commits?apiVersion=2022-11-28#compare-two-commits
+    /// Follows the `Link` header through every page, since a single comparison lists at most 250 commits.
+    pub fn get_commit_oids_in_range(&self, http_agent: &HttpProxyAgent, base: &str, head: &str)-> Result<Vec<String>, GittyError> {
+        let url = format!("{}/repos/{}/{}/compare/{}...{}", self.get_base_rest_url(), self.get_org_name(), self.get_repository_name(), base, head);
+        let mut request = self.build_compare_request(http_agent, &url)?
+            .query("per_page", "100");
+        let mut oids = Vec::new();
+        loop {
+            let response = request.call()?;
+            let next_page_url = get_next_page_url(response.header("Link"));
+            let string_response = response.into_string()
+                .map_err(|error| GittyError::Network(error.to_string()))?;
+            let comparison = serde_json::from_str::<Comparison>(&string_response)?;
+            oids.extend(comparison.commits.into_iter().map(|commit| commit.sha));
+            match next_page_url {
+                Some(next_page_url) => request = self.build_compare_request(http_agent, &next_page_url)?,
+                None if oids.len() < comparison.total_commits => return Err(GittyError::Deserialization(format!(
+                    "GitHub listed {} of the {} commits between {} and {}, approve a smaller range", oids.len(), comparison.total_commits, base, head))),
+                None => return Ok(oids),
+            }
+        }
+    }
+
+    pub fn post_comment_on_commit_that_you_approve_it(&self, http_agent: &HttpProxyAgent, commit: &Commit)-> Result<(), GittyError>{
+        self.post_approval_comment(http_agent, commit.get_id())
+    }
+
+    /// A comment POST is never resent blindly: after a failure that may have reached GitHub,
+    /// the commit's comments are checked first so the approval is not posted twice.
+    pub fn post_approval_comment(&self, http_agent: &HttpProxyAgent, oid: &str)-> Result<(), GittyError>{
+        let url = self.get_commit_comments_url(oid);
+        let retry_policy = http_agent.get_retry_policy();
+        let mut attempt = 1;
+        loop {
+            let result = http_agent.post(&url)
+                .set("Authorization",&self.get_bearer_token_string()?)
+                .set("X-GitHub-Api-Version", "2022-11-28")
+                .send_string(&serde_json::json!({"body": self.get_approval_message()}).to_string());
+            match result {
+                Ok(_) => return Ok(()),
+                // The agent already resends failures that never reached GitHub; these are the ones that might have.
+                Err(error) if attempt < retry_policy.get_max_attempts() && is_retryable(&error, true) && !is_retryable(&error, false) => {
+                    if self.has_approval_comment(http_agent, oid)? {
+                        return Ok(())
+                    }
+                    let delay = retry_policy.get_random_delay(attempt);
+                    println!("Approving {} failed: {}, retrying in {}ms", oid, error, delay.as_millis());
+                    std::thread::sleep(delay);
+                    attempt += 1;
                 },
-                Err(error) => panic!("{}", error),
-            };
-        filter_any_commits_that_do_not_match_collaborators(commits, collaborators)
-        
+                Err(error) => return Err(error.into()),
+            }
+        }
     }
 
-    pub fn post_comment_on_commit_that_you_approve_it(&self, http_agent: &HttpProxyAgent, commit: &Commit){
-        let url = format!("{}/repos/{}/{}/commits/{}/comments", self.get_base_rest_url(), self.get_org_name(), self.get_repository_name(),commit.get_id());
-        http_agent.post(&url)
-            .set("Authorization",&self.get_bearer_token_string())
+    //https://docs.github.com/en/rest/commits/comments?apiVersion=2022-11-28#list-commit-comments
+    pub fn has_approval_comment(&self, http_agent: &HttpProxyAgent, oid: &str)-> Result<bool, GittyError>{
+        let response = http_agent.get(&self.get_commit_comments_url(oid))
+            .set("Authorization",&self.get_bearer_token_string()?)
             .set("X-GitHub-Api-Version", "2022-11-28")
-            .send_string("{\"body\": \"I approve this\"}").unwrap();
+            .query("per_page", "100")
+            .call()?;
+        let string_response = response.into_string()
+            .map_err(|error| GittyError::Network(error.to_string()))?;
+        Ok(serde_json::from_str::<Vec<CommitComment>>(&string_response)?.iter()
+            .any(|comment| comment.body == self.get_approval_message()))
+    }
+
+    fn get_commit_comments_url(&self, oid: &str)-> String {
+        format!("{}/repos/{}/{}/commits/{}/comments", self.get_base_rest_url(), self.get_org_name(), self.get_repository_name(), oid)
     }
 }
 
+#[cfg(test)]
+pub mod testing {
+    /// A GraphQL history page holding `oids`, newest first, all by the collaborator with id `id 1`.
+    pub fn history_response(oids: &[&str])-> String {
+        history_page_response(oids, None)
+    }
+
+    /// Like `history_response`, but followed by another page at `end_cursor` when there is one.
+    pub fn history_page_response(oids: &[&str], end_cursor: Option<&str>)-> String {
+        let nodes: Vec<String> = oids.iter()
+            .map(|oid| format!(r#"{{"oid":"{}","message":"m","author":{{"user":{{"id":"id 1"}}}}}}"#, oid))
+            .collect();
+        let page_info = match end_cursor {
+            Some(end_cursor) => format!(r#"{{"hasNextPage":true,"endCursor":"{}"}}"#, end_cursor),
+            None => r#"{"hasNextPage":false,"endCursor":null}"#.to_string(),
+        };
+        format!(
+            "HTTP/1.1 200 OK\r\n\r\n{{\"data\":{{\"repository\":{{\"object\":{{\"history\":{{\"nodes\":[{}],\"pageInfo\":{}}}}}}}}}}}",
+            nodes.join(","), page_info
+        )
+    }
+}
 
 #[cfg(test)]
 mod tests{
     use std::env;
     use dotenv::dotenv;
     use chrono::{Local, NaiveDate};
-    use gix::Url;
+    use crate::{http_agent::testing::serve_responses, remote::RemoteLocation};
 
-    use super::*;
+    use super::{*, testing::{history_page_response, history_response}};
     #[derive(PartialEq, Eq, Debug, Serialize, Deserialize,Clone)]
     struct CommentResponse{
         id: u64,
@@ -155,7 +351,7 @@ mod tests{
         fn get_comments(&self, http_agent: &HttpProxyAgent, commit: &Commit)-> Vec<CommentResponse>{
             let comments_url = format!("{}/repos/{}/{}/commits/{}/comments", self.get_base_rest_url(), self.get_org_name(), self.get_repository_name(), commit.get_id());
             match http_agent.get(&comments_url)
-            .set("Authorization",&self.get_bearer_token_string())
+            .set("Authorization",&self.get_bearer_token_string().unwrap())
             .set("X-GitHub-Api-Version", "2022-11-28")
         .call()
             {
@@ -164,16 +360,16 @@ mod tests{
                 Err(error) => panic!("{}", error),
             }
         }
-        fn delete_comments(&self, http_agent: &HttpProxyAgent, comments: &Vec<CommentResponse>){
+        fn delete_comments(&self, comments: &Vec<CommentResponse>){
             comments.iter().for_each(|comment| {
-                http_agent.delete(
+                ureq::delete(
                     &format!("{}/repos/{}/{}/comments/{}", 
                     &self.get_base_rest_url(), 
                     self.get_org_name(), 
                     self.get_repository_name(), 
                     comment.get_id()
                 ))
-                .set("Authorization",&self.get_bearer_token_string())
+                .set("Authorization",&self.get_bearer_token_string().unwrap())
                 .set("X-GitHub-Api-Version", "2022-11-28")
                 .call().expect("failed to delete comment");
             })
@@ -192,10 +388,12 @@ mod tests{
         let github_token = env::var("github_token").expect("No environment variable found for github_token");
         let http_agent = HttpProxyAgent::new_with_proxy("");
         let repository = {
-            let token = github_token;let url = Url::try_from("git@github.com:bfrazho/gitty.git").unwrap();
+            let token = github_token;let url = RemoteLocation::parse("git@github.com:bfrazho/gitty.git").unwrap();
             GitRepository::new(token, url, "main".to_string())
         };
-        let commits = repository.get_commits_matching_collaborators_since_timestamp(&http_agent, &collaborators, timestamp);
+        let commits: Vec<Commit> = repository.get_branch_commits_since_timestamp(&http_agent, "main", timestamp).unwrap().into_iter()
+            .filter(|commit| commit.is_authored_by_any_of(&collaborators))
+            .collect();
         println!("{:?}", commits);
         assert!(
             commits.contains(&Commit{
@@ -204,6 +402,112 @@ mod tests{
                 author: Author { user: Some(User{id: Some("MDQ6VXNlcjMxMzkxNTc5".to_string())})}
             }));
     }
+    #[test]
+    fn can_get_commits_since_oid() {
+        let (base_url, server) = serve_responses(vec![
+            history_response(&["c3", "c2", "c1", "c0"]),
+            history_response(&["r2", "r1"]),
+        ]);
+        let http_agent = HttpProxyAgent::new_with_proxy("");
+        let repository = GitRepository::new("token".to_string(), RemoteLocation::parse(&format!("{}/bfrazho/gitty", base_url)).unwrap(), "main".to_string());
+
+        let commits = repository.get_branch_commits_since_oid(&http_agent, "main", "c1").unwrap().unwrap();
+        assert_eq!(vec!["c3", "c2"], commits.iter().map(Commit::get_id).collect::<Vec<&str>>());
+        assert_eq!(None, repository.get_branch_commits_since_oid(&http_agent, "main", "c1").unwrap(), "c1 was force-pushed away");
+        assert!(!server.join().unwrap()[0].contains("since"));
+    }
+
+    #[test]
+    fn page_limit_before_last_seen_oid_is_an_error() {
+        let (base_url, server) = serve_responses(vec![
+            history_page_response(&["c5", "c4"], Some("page 2")),
+            history_page_response(&["c3", "c2"], Some("page 3")),
+        ]);
+        let http_agent = HttpProxyAgent::new_with_proxy("");
+        let repository = GitRepository::new("token".to_string(), RemoteLocation::parse(&format!("{}/bfrazho/gitty", base_url)).unwrap(), "main".to_string())
+            .with_max_history_pages(2);
+
+        let result = repository.get_branch_commits_since_oid(&http_agent, "main", "c1");
+        assert!(matches!(result, Err(GittyError::Config(message)) if message.contains("max_history_pages")), "not mistaken for a force-push");
+        assert!(server.join().unwrap()[1].contains("after: \\\"page 2\\\""));
+    }
+
+    fn comparison_response(oids: &[&str], total_commits: usize, next_page_url: Option<&str>)-> String {
+        let commits: Vec<String> = oids.iter().map(|oid| format!(r#"{{"sha":"{}"}}"#, oid)).collect();
+        let link = next_page_url.map_or(String::new(), |next_page_url| format!("Link: <{}>; rel=\"next\"\r\n", next_page_url));
+        format!("HTTP/1.1 200 OK\r\n{}\r\n{{\"total_commits\":{},\"commits\":[{}]}}", link, total_commits, commits.join(","))
+    }
+
+    #[test]
+    fn commit_range_follows_every_page() {
+        let (second_page_url, second_page) = serve_responses(vec![comparison_response(&["c3"], 3, None)]);
+        let (base_url, first_page) = serve_responses(vec![comparison_response(&["c1", "c2"], 3, Some(&format!("{}/page/2", second_page_url)))]);
+        let http_agent = HttpProxyAgent::new_with_proxy("");
+        let repository = GitRepository::new("token".to_string(), RemoteLocation::parse(&format!("{}/bfrazho/gitty", base_url)).unwrap(), "main".to_string());
+
+        assert_eq!(vec!["c1", "c2", "c3"], repository.get_commit_oids_in_range(&http_agent, "c0", "c3").unwrap());
+        assert!(first_page.join().unwrap()[0].contains("per_page=100"));
+        assert!(second_page.join().unwrap()[0].starts_with("GET /page/2 "));
+    }
+
+    #[test]
+    fn commit_range_missing_commits_is_an_error() {
+        let (base_url, server) = serve_responses(vec![comparison_response(&["c1", "c2"], 300, None)]);
+        let http_agent = HttpProxyAgent::new_with_proxy("");
+        let repository = GitRepository::new("token".to_string(), RemoteLocation::parse(&format!("{}/bfrazho/gitty", base_url)).unwrap(), "main".to_string());
+
+        assert!(matches!(repository.get_commit_oids_in_range(&http_agent, "c0", "c300"), Err(GittyError::Deserialization(_))));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn head_query_only_includes_cursor_when_paging() {
+        let repository = GitRepository::new("token".to_string(), RemoteLocation::parse("git@github.com:bfrazho/gitty.git").unwrap(), "main".to_string());
+
+        assert!(repository.build_get_commits_from_head_query("main", None).contains("history(first: 100)"));
+        assert!(repository.build_get_commits_from_head_query("main", Some("abc 99")).contains("history(first: 100, after: \\\"abc 99\\\")"));
+        assert!(repository.build_get_commits_from_head_query("release/1.2", None).contains("object(expression: \\\"release/1.2\\\")"));
+    }
+
+    #[test]
+    fn branch_names_are_escaped_in_the_query() {
+        let repository = GitRepository::new("token".to_string(), RemoteLocation::parse("git@github.com:bfrazho/gitty.git").unwrap(), "main".to_string());
+        let query = repository.build_get_commits_from_head_query("release/\"1.2\"", None);
+
+        let body: serde_json::Value = serde_json::from_str(&query).unwrap();
+        assert!(body["query"].as_str().unwrap().contains(r#"object(expression: "release/\"1.2\"")"#));
+    }
+
+    #[test]
+    fn missing_branch_is_not_found() {
+        let (base_url, server) = serve_responses(vec![
+            "HTTP/1.1 200 OK\r\n\r\n{\"data\":{\"repository\":{\"object\":null}}}".to_string(),
+        ]);
+        let http_agent = HttpProxyAgent::new_with_proxy("");
+        let repository = GitRepository::new("token".to_string(), RemoteLocation::parse(&format!("{}/bfrazho/gitty", base_url)).unwrap(), "main".to_string());
+
+        assert!(matches!(repository.get_branch_head_oid(&http_agent, "release/gone"), Err(GittyError::NotFound(_))));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn timestamp_query_requests_page_info_and_cursor() {
+        let repository = GitRepository::new("token".to_string(), RemoteLocation::parse("git@github.com:bfrazho/gitty.git").unwrap(), "main".to_string());
+        let timestamp = NaiveDate::from_ymd_opt(2023, 10, 7).unwrap()
+            .and_hms_opt(0, 0, 0).unwrap()
+            .and_local_timezone(Local::now().timezone()).unwrap();
+        let query = repository.build_get_commits_after_timestamp_query("main", timestamp, Some("abc 99"));
+
+        assert!(query.contains(&format!("history(first: 100, since: \\\"{}\\\", after: \\\"abc 99\\\")", timestamp.to_rfc3339())));
+        assert!(query.contains("pageInfo"));
+    }
+
+    #[test]
+    fn can_deserialize_history_page_info() {
+        let history = serde_json::from_str::<History>(r#"{"nodes": [], "pageInfo": {"hasNextPage": true, "endCursor": "abc 99"}}"#).unwrap();
+        assert_eq!(PageInfo { has_next_page: true, end_cursor: Some("abc 99".to_string()) }, history.page_info);
+    }
+
     #[test]
     fn can_add_comment(){
         let commit = Commit{
@@ -215,17 +519,17 @@ mod tests{
         dotenv().ok();
         let github_token = env::var("github_token").expect("No environment variable found for github_token");
         let repository = {
-            let token = github_token;let url = Url::try_from("git@github.com:bfrazho/gitty.git").unwrap();
+            let token = github_token;let url = RemoteLocation::parse("git@github.com:bfrazho/gitty.git").unwrap();
             GitRepository::new(token, url, "main".to_string())
         };
         let http_agent = HttpProxyAgent::new_with_proxy("");
 
-        repository.post_comment_on_commit_that_you_approve_it(&http_agent, &commit);
+        repository.post_comment_on_commit_that_you_approve_it(&http_agent, &commit).unwrap();
 
         let comments = repository.get_comments(&http_agent, &commit);
         assert_eq!("I approve this", comments.get(0).unwrap().get_body());
         
-        repository.delete_comments(&http_agent, &comments);
+        repository.delete_comments(&comments);
     }
 
 }
\ No newline at end of file
@@ -0,0 +1,600 @@
+use std::{collections::BTreeMap, env, fs, path::{Path, PathBuf}, time::Duration};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{cli::Cli, collaborator::CollaboratorPermission, credential_store::CredentialStoreKind, error::GittyError, profile::ProfileSettings, repository::DEFAULT_MAX_HISTORY_PAGES, retry::{RetryPolicy, DEFAULT_REQUEST_TIMEOUT_SECONDS, DEFAULT_RETRY_INITIAL_BACKOFF_MS, DEFAULT_RETRY_JITTER_PERCENT, DEFAULT_RETRY_MAX_ATTEMPTS, DEFAULT_RETRY_MAX_BACKOFF_MS}, tls::TlsSettings};
+
+pub const DEFAULT_POLL_INTERVAL_SECONDS: u64 = 300;
+pub const DEFAULT_APPROVAL_MESSAGE: &str = "I approve this";
+pub const DEFAULT_TOKEN_EXPIRY_WARNING_DAYS: i64 = 14;
+pub const REPO_CONFIG_FILE_NAME: &str = ".gitty.toml";
+
+/// One source of settings. Every field is optional so a layer only overrides what it sets.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigLayer {
+    remote: Option<String>,
+    watch_fork_and_upstream: Option<bool>,
+    workspace_repositories: Option<Vec<String>>,
+    workspace_dir: Option<PathBuf>,
+    branch: Option<String>,
+    default_branches: Option<Vec<String>>,
+    watch_branches: Option<Vec<String>>,
+    poll_interval_seconds: Option<u64>,
+    approval_message: Option<String>,
+    collaborator_permission: Option<String>,
+    max_history_pages: Option<usize>,
+    profile: Option<String>,
+    profiles: Option<BTreeMap<String, ProfileSettings>>,
+    credential_store: Option<String>,
+    token_expiry_warning_days: Option<i64>,
+    github_app_id: Option<u64>,
+    github_app_private_key: Option<PathBuf>,
+    oauth_client_id: Option<String>,
+    proxy: Option<String>,
+    proxy_rules: Option<BTreeMap<String, String>>,
+    tls: Option<BTreeMap<String, TlsSettings>>,
+    retry_max_attempts: Option<u32>,
+    retry_initial_backoff_ms: Option<u64>,
+    retry_max_backoff_ms: Option<u64>,
+    retry_jitter_percent: Option<u32>,
+    request_timeout_seconds: Option<u64>,
+    trace_http: Option<PathBuf>,
+    trace_http_bodies: Option<bool>,
+}
+
+impl ConfigLayer {
+    /// Settings from `higher` win over the ones already in `self`.
+    pub fn merge(self, higher: ConfigLayer)-> ConfigLayer {
+        ConfigLayer {
+            remote: higher.remote.or(self.remote),
+            watch_fork_and_upstream: higher.watch_fork_and_upstream.or(self.watch_fork_and_upstream),
+            workspace_repositories: higher.workspace_repositories.or(self.workspace_repositories),
+            workspace_dir: higher.workspace_dir.or(self.workspace_dir),
+            branch: higher.branch.or(self.branch),
+            default_branches: higher.default_branches.or(self.default_branches),
+            watch_branches: higher.watch_branches.or(self.watch_branches),
+            poll_interval_seconds: higher.poll_interval_seconds.or(self.poll_interval_seconds),
+            approval_message: higher.approval_message.or(self.approval_message),
+            collaborator_permission: higher.collaborator_permission.or(self.collaborator_permission),
+            max_history_pages: higher.max_history_pages.or(self.max_history_pages),
+            profile: higher.profile.or(self.profile),
+            profiles: match (self.profiles, higher.profiles) {
+                (Some(mut profiles), Some(higher_profiles)) => {
+                    profiles.extend(higher_profiles);
+                    Some(profiles)
+                },
+                (profiles, higher_profiles) => higher_profiles.or(profiles),
+            },
+            credential_store: higher.credential_store.or(self.credential_store),
+            token_expiry_warning_days: higher.token_expiry_warning_days.or(self.token_expiry_warning_days),
+            github_app_id: higher.github_app_id.or(self.github_app_id),
+            github_app_private_key: higher.github_app_private_key.or(self.github_app_private_key),
+            oauth_client_id: higher.oauth_client_id.or(self.oauth_client_id),
+            proxy: higher.proxy.or(self.proxy),
+            proxy_rules: match (self.proxy_rules, higher.proxy_rules) {
+                (Some(mut proxy_rules), Some(higher_proxy_rules)) => {
+                    proxy_rules.extend(higher_proxy_rules);
+                    Some(proxy_rules)
+                },
+                (proxy_rules, higher_proxy_rules) => higher_proxy_rules.or(proxy_rules),
+            },
+            tls: match (self.tls, higher.tls) {
+                (Some(mut tls), Some(higher_tls)) => {
+                    tls.extend(higher_tls);
+                    Some(tls)
+                },
+                (tls, higher_tls) => higher_tls.or(tls),
+            },
+            retry_max_attempts: higher.retry_max_attempts.or(self.retry_max_attempts),
+            retry_initial_backoff_ms: higher.retry_initial_backoff_ms.or(self.retry_initial_backoff_ms),
+            retry_max_backoff_ms: higher.retry_max_backoff_ms.or(self.retry_max_backoff_ms),
+            retry_jitter_percent: higher.retry_jitter_percent.or(self.retry_jitter_percent),
+            request_timeout_seconds: higher.request_timeout_seconds.or(self.request_timeout_seconds),
+            trace_http: higher.trace_http.or(self.trace_http),
+            trace_http_bodies: higher.trace_http_bodies.or(self.trace_http_bodies),
+        }
+    }
+
+    pub fn from_file(path: &Path)-> Result<ConfigLayer, GittyError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|error| GittyError::Config(format!("failed to read {}: {}", path.display(), error)))?;
+        toml::from_str(&contents)
+            .map_err(|error| GittyError::Config(format!("failed to parse {}: {}", path.display(), error)))
+    }
+
+    /// A `.gitty.toml` comes with whatever repository is checked out, so it may only tune how gitty watches.
+    /// Settings that pick credentials, route or trace requests, or write files are dropped with a warning.
+    pub fn from_repo_file(path: &Path)-> Result<ConfigLayer, GittyError> {
+        let mut layer = ConfigLayer::from_file(path)?;
+        let mut ignored = Vec::new();
+        macro_rules! drop_user_only_settings {
+            ($($field:ident),*) => {
+                $(if layer.$field.take().is_some() { ignored.push(stringify!($field)); })*
+            };
+        }
+        drop_user_only_settings!(profile, profiles, credential_store, github_app_id, github_app_private_key, oauth_client_id,
+            proxy, proxy_rules, tls, trace_http, trace_http_bodies);
+        if !ignored.is_empty() {
+            eprintln!("Ignoring {} in {}, set them in your user config or environment instead", ignored.join(", "), path.display());
+        }
+        Ok(layer)
+    }
+
+    pub fn from_env(get_var: impl Fn(&str)-> Option<String>)-> Result<ConfigLayer, GittyError> {
+        Ok(ConfigLayer {
+            remote: get_var("GITTY_REMOTE"),
+            watch_fork_and_upstream: parse_env_bool(&get_var, "GITTY_WATCH_FORK_AND_UPSTREAM")?,
+            workspace_repositories: get_var("GITTY_WORKSPACE_REPOSITORIES")
+                .map(|repositories| repositories.split(',').map(|repository| repository.trim().to_string()).filter(|repository| !repository.is_empty()).collect()),
+            workspace_dir: get_var("GITTY_WORKSPACE_DIR").map(PathBuf::from),
+            branch: get_var("GITTY_BRANCH"),
+            default_branches: get_var("GITTY_DEFAULT_BRANCHES")
+                .map(|branches| branches.split(',').map(|branch| branch.trim().to_string()).filter(|branch| !branch.is_empty()).collect()),
+            watch_branches: get_var("GITTY_WATCH_BRANCHES")
+                .map(|branches| branches.split(',').map(|branch| branch.trim().to_string()).filter(|branch| !branch.is_empty()).collect()),
+            poll_interval_seconds: parse_env_number(&get_var, "GITTY_POLL_INTERVAL_SECONDS")?,
+            approval_message: get_var("GITTY_APPROVAL_MESSAGE"),
+            collaborator_permission: get_var("GITTY_COLLABORATOR_PERMISSION"),
+            max_history_pages: parse_env_number(&get_var, "GITTY_MAX_HISTORY_PAGES")?,
+            profile: get_var("GITTY_PROFILE"),
+            profiles: None,
+            credential_store: get_var("GITTY_CREDENTIAL_STORE"),
+            token_expiry_warning_days: parse_env_number(&get_var, "GITTY_TOKEN_EXPIRY_WARNING_DAYS")?,
+            github_app_id: parse_env_number(&get_var, "GITTY_GITHUB_APP_ID")?,
+            github_app_private_key: get_var("GITTY_GITHUB_APP_PRIVATE_KEY").map(PathBuf::from),
+            oauth_client_id: get_var("GITTY_OAUTH_CLIENT_ID"),
+            proxy: get_var("GITTY_PROXY"),
+            proxy_rules: None,
+            tls: None,
+            retry_max_attempts: parse_env_number(&get_var, "GITTY_RETRY_MAX_ATTEMPTS")?,
+            retry_initial_backoff_ms: parse_env_number(&get_var, "GITTY_RETRY_INITIAL_BACKOFF_MS")?,
+            retry_max_backoff_ms: parse_env_number(&get_var, "GITTY_RETRY_MAX_BACKOFF_MS")?,
+            retry_jitter_percent: parse_env_number(&get_var, "GITTY_RETRY_JITTER_PERCENT")?,
+            request_timeout_seconds: parse_env_number(&get_var, "GITTY_REQUEST_TIMEOUT_SECONDS")?,
+            trace_http: get_var("GITTY_TRACE_HTTP").map(PathBuf::from),
+            trace_http_bodies: None,
+        })
+    }
+}
+
+impl From<&Cli> for ConfigLayer {
+    fn from(cli: &Cli) -> Self {
+        ConfigLayer {
+            remote: cli.remote.clone(),
+            watch_fork_and_upstream: cli.watch_fork_and_upstream.then_some(true),
+            branch: cli.branch.clone(),
+            poll_interval_seconds: cli.interval,
+            profile: cli.profile.clone(),
+            trace_http: cli.trace_http.clone(),
+            trace_http_bodies: cli.trace_http_bodies.then_some(true),
+            ..ConfigLayer::default()
+        }
+    }
+}
+
+fn parse_env_number<T: std::str::FromStr>(get_var: &impl Fn(&str)-> Option<String>, name: &str)-> Result<Option<T>, GittyError> {
+    match get_var(name) {
+        Some(value) => value.trim().parse().map(Some)
+            .map_err(|_| GittyError::Config(format!("{} must be a number, got \"{}\"", name, value))),
+        None => Ok(None),
+    }
+}
+
+fn parse_env_bool(get_var: &impl Fn(&str)-> Option<String>, name: &str)-> Result<Option<bool>, GittyError> {
+    match get_var(name) {
+        Some(value) => match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" => Ok(Some(true)),
+            "0" | "false" | "no" => Ok(Some(false)),
+            _ => Err(GittyError::Config(format!("{} must be true or false, got \"{}\"", name, value))),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The settings gitty runs with after every layer has been merged.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Config {
+    repo_path: PathBuf,
+    remote: Option<String>,
+    watch_fork_and_upstream: bool,
+    workspace_repositories: Vec<String>,
+    workspace_dir: Option<PathBuf>,
+    branch: Option<String>,
+    default_branches: Vec<String>,
+    watch_branches: Vec<String>,
+    poll_interval_seconds: u64,
+    approval_message: String,
+    collaborator_permission: CollaboratorPermission,
+    max_history_pages: usize,
+    profile: Option<String>,
+    profiles: BTreeMap<String, ProfileSettings>,
+    credential_store: Option<CredentialStoreKind>,
+    token_expiry_warning_days: i64,
+    github_app: Option<(u64, PathBuf)>,
+    oauth_client_id: Option<String>,
+    proxy: Option<String>,
+    proxy_rules: BTreeMap<String, String>,
+    tls: BTreeMap<String, TlsSettings>,
+    retry_policy: RetryPolicy,
+    trace_http: Option<PathBuf>,
+    trace_http_bodies: bool,
+    sources: Vec<PathBuf>,
+}
+
+impl Config {
+    pub fn resolve(repo_path: PathBuf, layer: ConfigLayer, sources: Vec<PathBuf>)-> Result<Config, GittyError> {
+        Ok(Config {
+            repo_path,
+            remote: layer.remote,
+            watch_fork_and_upstream: layer.watch_fork_and_upstream.unwrap_or(false),
+            workspace_repositories: layer.workspace_repositories.unwrap_or_default(),
+            workspace_dir: layer.workspace_dir,
+            branch: layer.branch,
+            default_branches: layer.default_branches.unwrap_or_else(|| vec!["main".to_string(), "master".to_string()]),
+            watch_branches: layer.watch_branches.unwrap_or_default(),
+            poll_interval_seconds: layer.poll_interval_seconds.unwrap_or(DEFAULT_POLL_INTERVAL_SECONDS),
+            approval_message: layer.approval_message.unwrap_or_else(|| DEFAULT_APPROVAL_MESSAGE.to_string()),
+            collaborator_permission: match layer.collaborator_permission {
+                Some(permission) => permission.parse()?,
+                None => CollaboratorPermission::default(),
+            },
+            max_history_pages: layer.max_history_pages.unwrap_or(DEFAULT_MAX_HISTORY_PAGES),
+            profile: layer.profile,
+            profiles: layer.profiles.unwrap_or_default(),
+            credential_store: layer.credential_store.map(|kind| kind.parse()).transpose()?,
+            token_expiry_warning_days: layer.token_expiry_warning_days.unwrap_or(DEFAULT_TOKEN_EXPIRY_WARNING_DAYS),
+            github_app: match (layer.github_app_id, layer.github_app_private_key) {
+                (Some(app_id), Some(private_key)) => Some((app_id, private_key)),
+                (None, None) => None,
+                _ => return Err(GittyError::Config("github_app_id and github_app_private_key must be set together".to_string())),
+            },
+            oauth_client_id: layer.oauth_client_id,
+            proxy: layer.proxy,
+            proxy_rules: layer.proxy_rules.unwrap_or_default(),
+            tls: layer.tls.unwrap_or_default(),
+            retry_policy: RetryPolicy::new(
+                layer.retry_max_attempts.unwrap_or(DEFAULT_RETRY_MAX_ATTEMPTS),
+                Duration::from_millis(layer.retry_initial_backoff_ms.unwrap_or(DEFAULT_RETRY_INITIAL_BACKOFF_MS)),
+                Duration::from_millis(layer.retry_max_backoff_ms.unwrap_or(DEFAULT_RETRY_MAX_BACKOFF_MS)),
+                layer.retry_jitter_percent.unwrap_or(DEFAULT_RETRY_JITTER_PERCENT),
+                Duration::from_secs(layer.request_timeout_seconds.unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECONDS)),
+            ),
+            trace_http: layer.trace_http,
+            trace_http_bodies: layer.trace_http_bodies.unwrap_or(false),
+            sources,
+        })
+    }
+
+    pub fn get_repo_path(&self)-> &Path {
+        &self.repo_path
+    }
+    pub fn get_remote(&self)-> Option<&str> {
+        self.remote.as_deref()
+    }
+    /// Whether a fork's upstream is watched alongside the fork instead of in place of it.
+    pub fn get_watch_fork_and_upstream(&self)-> bool {
+        self.watch_fork_and_upstream
+    }
+    /// `owner/repo` names, or remote urls, watched by `gitty workspace`.
+    pub fn get_workspace_repositories(&self)-> &[String] {
+        &self.workspace_repositories
+    }
+    /// A directory whose git checkouts `gitty workspace` watches.
+    pub fn get_workspace_dir(&self)-> Option<&Path> {
+        self.workspace_dir.as_deref()
+    }
+    pub fn get_branch(&self)-> Option<&str> {
+        self.branch.as_deref()
+    }
+    pub fn get_default_branches(&self)-> &[String] {
+        &self.default_branches
+    }
+    /// Names or glob patterns such as `release/*` of branches watched as well as the main branch.
+    pub fn get_watch_branches(&self)-> &[String] {
+        &self.watch_branches
+    }
+    pub fn get_poll_interval_seconds(&self)-> u64 {
+        self.poll_interval_seconds
+    }
+    pub fn get_approval_message(&self)-> &str {
+        &self.approval_message
+    }
+    pub fn get_collaborator_permission(&self)-> CollaboratorPermission {
+        self.collaborator_permission
+    }
+    pub fn get_max_history_pages(&self)-> usize {
+        self.max_history_pages
+    }
+    pub fn get_profile(&self)-> Option<&str> {
+        self.profile.as_deref()
+    }
+    pub fn get_profiles(&self)-> &BTreeMap<String, ProfileSettings> {
+        &self.profiles
+    }
+    /// `None` means use whichever store the profile already has.
+    pub fn get_credential_store(&self)-> Option<CredentialStoreKind> {
+        self.credential_store
+    }
+    pub fn get_token_expiry_warning_days(&self)-> i64 {
+        self.token_expiry_warning_days
+    }
+    /// The app id and private key path when approving as a GitHub App instead of with a personal token.
+    pub fn get_github_app(&self)-> Option<(u64, &Path)> {
+        self.github_app.as_ref().map(|(app_id, private_key)| (*app_id, private_key.as_path()))
+    }
+    /// Client id of the OAuth app `gitty login` authorizes, registered on the repository's host.
+    pub fn get_oauth_client_id(&self)-> Option<&str> {
+        self.oauth_client_id.as_deref()
+    }
+    /// Proxy for every host without a rule, `direct` to ignore the proxy environment variables.
+    pub fn get_proxy(&self)-> Option<&str> {
+        self.proxy.as_deref()
+    }
+    /// Host pattern to proxy url, or `direct`.
+    pub fn get_proxy_rules(&self)-> &BTreeMap<String, String> {
+        &self.proxy_rules
+    }
+    /// Host pattern, or `*` for every other host, to the CAs and client certificate used for it.
+    pub fn get_tls_settings(&self)-> &BTreeMap<String, TlsSettings> {
+        &self.tls
+    }
+    pub fn get_retry_policy(&self)-> &RetryPolicy {
+        &self.retry_policy
+    }
+    pub fn get_trace_http(&self)-> Option<&Path> {
+        self.trace_http.as_deref()
+    }
+    pub fn get_trace_http_bodies(&self)-> bool {
+        self.trace_http_bodies
+    }
+    /// Config files that were found and merged, lowest precedence first.
+    pub fn get_sources(&self)-> &[PathBuf] {
+        &self.sources
+    }
+}
+
+/// `$XDG_CONFIG_HOME/gitty`, falling back to `~/.config/gitty` (or `%APPDATA%\gitty` on Windows).
+pub fn get_config_dir()-> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(base.join("gitty"))
+}
+
+/// `$XDG_CACHE_HOME/gitty`, for things that can be thrown away and fetched again.
+pub fn get_cache_dir()-> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+    Some(base.join("gitty"))
+}
+
+/// `$XDG_STATE_HOME/gitty`, falling back to `~/.local/state/gitty` (or `%LOCALAPPDATA%\gitty` on Windows).
+pub fn get_state_dir()-> Option<PathBuf> {
+    let base = env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+    Some(base.join("gitty"))
+}
+
+pub fn get_user_config_path()-> Option<PathBuf> {
+    get_config_dir().map(|dir| dir.join("config.toml"))
+}
+
+/// Looks for `.gitty.toml` from `start` up to the root of the git checkout it is in.
+pub fn find_repo_config_path(start: &Path)-> Option<PathBuf> {
+    let start = start.canonicalize().ok()?;
+    for dir in start.ancestors() {
+        let candidate = dir.join(REPO_CONFIG_FILE_NAME);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        if dir.join(".git").exists() {
+            return None;
+        }
+    }
+    None
+}
+
+/// Merges, from lowest to highest precedence: the repository's `.gitty.toml`, the user's
+/// `config.toml`, `GITTY_*` environment variables and command-line flags.
+pub fn load_config(cli: &Cli)-> Result<Config, GittyError> {
+    let mut layer = ConfigLayer::default();
+    let mut sources = Vec::new();
+    if let Some(path) = find_repo_config_path(&cli.repo) {
+        layer = layer.merge(ConfigLayer::from_repo_file(&path)?);
+        sources.push(path);
+    }
+    if let Some(path) = get_user_config_path().filter(|path| path.is_file()) {
+        layer = layer.merge(ConfigLayer::from_file(&path)?);
+        sources.push(path);
+    }
+    layer = layer.merge(ConfigLayer::from_env(|name| env::var(name).ok())?);
+    layer = layer.merge(ConfigLayer::from(cli));
+    Config::resolve(cli.repo.clone(), layer, sources)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, fs};
+
+    use clap::Parser;
+
+    use super::*;
+
+    fn config_from(layer: ConfigLayer)-> Config {
+        Config::resolve(PathBuf::from("."), layer, Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn defaults_match_previous_behaviour() {
+        let config = config_from(ConfigLayer::default());
+        assert_eq!(300, config.get_poll_interval_seconds());
+        assert_eq!("I approve this", config.get_approval_message());
+        assert_eq!(CollaboratorPermission::Admin, config.get_collaborator_permission());
+        assert_eq!(&["main".to_string(), "master".to_string()], config.get_default_branches());
+        assert_eq!(DEFAULT_MAX_HISTORY_PAGES, config.get_max_history_pages());
+    }
+
+    #[test]
+    fn higher_layers_win() {
+        let repo_layer: ConfigLayer = toml::from_str(r#"
+            poll_interval_seconds = 600
+            approval_message = "LGTM"
+            collaborator_permission = "push"
+        "#).unwrap();
+        let user_layer: ConfigLayer = toml::from_str(r#"
+            poll_interval_seconds = 60
+        "#).unwrap();
+        let cli_layer = ConfigLayer::from(&Cli::parse_from(["gitty", "--branch", "develop"]));
+
+        let config = config_from(repo_layer.merge(user_layer).merge(cli_layer));
+
+        assert_eq!(60, config.get_poll_interval_seconds());
+        assert_eq!("LGTM", config.get_approval_message());
+        assert_eq!(CollaboratorPermission::Push, config.get_collaborator_permission());
+        assert_eq!(Some("develop"), config.get_branch());
+    }
+
+    #[test]
+    fn can_read_environment_layer() {
+        let vars = HashMap::from([
+            ("GITTY_POLL_INTERVAL_SECONDS", "30"),
+            ("GITTY_DEFAULT_BRANCHES", "trunk, develop"),
+            ("GITTY_WATCH_BRANCHES", "release/*,hotfix/*"),
+            ("GITTY_WORKSPACE_REPOSITORIES", "team/api, team/web"),
+            ("GITTY_WATCH_FORK_AND_UPSTREAM", "true"),
+        ]);
+        let layer = ConfigLayer::from_env(|name| vars.get(name).map(|value| value.to_string())).unwrap();
+        let config = config_from(layer);
+
+        assert_eq!(30, config.get_poll_interval_seconds());
+        assert_eq!(&["trunk".to_string(), "develop".to_string()], config.get_default_branches());
+        assert_eq!(&["release/*".to_string(), "hotfix/*".to_string()], config.get_watch_branches());
+        assert_eq!(&["team/api".to_string(), "team/web".to_string()], config.get_workspace_repositories());
+        assert!(config.get_watch_fork_and_upstream());
+    }
+
+    #[test]
+    fn repo_config_cannot_choose_credentials_or_routing() {
+        let dir = env::temp_dir().join("gitty_repo_config_cannot_choose_credentials_or_routing");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(REPO_CONFIG_FILE_NAME);
+        fs::write(&path, r#"
+            poll_interval_seconds = 600
+            watch_branches = ["release/*"]
+            profile = "work"
+            credential_store = "plaintext"
+            github_app_id = 1
+            github_app_private_key = "/tmp/key.pem"
+            oauth_client_id = "abc"
+            proxy = "http://proxy.example.com:8080"
+            trace_http = "/tmp/trace.log"
+            trace_http_bodies = true
+            [proxy_rules]
+            "github.com" = "direct"
+            [tls."github.com"]
+            ca_bundles = ["/tmp/ca.pem"]
+        "#).unwrap();
+
+        let layer = ConfigLayer::from_repo_file(&path).unwrap();
+        let expected: ConfigLayer = toml::from_str(r#"
+            poll_interval_seconds = 600
+            watch_branches = ["release/*"]
+        "#).unwrap();
+
+        assert_eq!(expected, layer);
+    }
+
+    #[test]
+    fn invalid_environment_numbers_are_config_errors() {
+        let result = ConfigLayer::from_env(|name| (name == "GITTY_MAX_HISTORY_PAGES").then(|| "lots".to_string()));
+        assert!(matches!(result, Err(GittyError::Config(_))));
+    }
+
+    #[test]
+    fn profiles_from_every_layer_are_kept() {
+        let repo_layer: ConfigLayer = toml::from_str(r#"
+            [profiles.work]
+            host = "github.some-business.com"
+        "#).unwrap();
+        let user_layer: ConfigLayer = toml::from_str(r#"
+            profile = "personal"
+            [profiles.personal]
+            host = "github.com"
+        "#).unwrap();
+
+        let config = config_from(repo_layer.merge(user_layer));
+
+        assert_eq!(Some("personal"), config.get_profile());
+        assert_eq!(vec!["personal", "work"], config.get_profiles().keys().collect::<Vec<&String>>());
+    }
+
+    #[test]
+    fn github_app_needs_id_and_private_key() {
+        let layer: ConfigLayer = toml::from_str(r#"
+            github_app_id = 1234
+            github_app_private_key = "/keys/gitty.pem"
+        "#).unwrap();
+        assert_eq!(Some((1234, Path::new("/keys/gitty.pem"))), config_from(layer).get_github_app());
+
+        let layer: ConfigLayer = toml::from_str("github_app_id = 1234").unwrap();
+        assert!(matches!(Config::resolve(PathBuf::from("."), layer, Vec::new()), Err(GittyError::Config(_))));
+    }
+
+    #[test]
+    fn can_read_tls_settings_per_host() {
+        let layer: ConfigLayer = toml::from_str(r#"
+            [tls."github.some-business.com"]
+            ca_bundles = ["/etc/ssl/corp.pem"]
+            system_roots = true
+            client_certificate = "/keys/client.pem"
+            client_key = "/keys/client.key"
+        "#).unwrap();
+
+        let config = config_from(layer);
+
+        assert_eq!(
+            Some(&TlsSettings::new(vec![PathBuf::from("/etc/ssl/corp.pem")], true, Some(PathBuf::from("/keys/client.pem")), Some(PathBuf::from("/keys/client.key")))),
+            config.get_tls_settings().get("github.some-business.com")
+        );
+    }
+
+    #[test]
+    fn can_configure_retry_policy() {
+        let layer: ConfigLayer = toml::from_str(r#"
+            retry_max_attempts = 5
+            retry_initial_backoff_ms = 250
+            request_timeout_seconds = 10
+        "#).unwrap();
+        let env_layer = ConfigLayer::from_env(|name| (name == "GITTY_RETRY_JITTER_PERCENT").then(|| "0".to_string())).unwrap();
+
+        let config = config_from(layer.merge(env_layer));
+
+        assert_eq!(
+            &RetryPolicy::new(5, Duration::from_millis(250), Duration::from_millis(DEFAULT_RETRY_MAX_BACKOFF_MS), 0, Duration::from_secs(10)),
+            config.get_retry_policy()
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(toml::from_str::<ConfigLayer>("pol_interval_seconds = 5").is_err());
+    }
+
+    #[test]
+    fn finds_repo_config_in_checkout_root() {
+        let root = env::temp_dir().join("gitty_repo_config_test");
+        let nested = root.join("src").join("nested");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join(REPO_CONFIG_FILE_NAME), "branch = \"trunk\"").unwrap();
+
+        let found = find_repo_config_path(&nested).unwrap();
+        assert_eq!(root.canonicalize().unwrap().join(REPO_CONFIG_FILE_NAME), found);
+        assert_eq!(Some("trunk"), config_from(ConfigLayer::from_file(&found).unwrap()).get_branch());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,299 @@
+use std::{collections::BTreeMap, env, fs, io::ErrorKind, path::PathBuf, str::FromStr};
+
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chacha20poly1305::{aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng}, ChaCha20Poly1305, Key, Nonce};
+use serde::{Serialize, Deserialize};
+
+use crate::{error::GittyError, profile::{read_with_legacy_fallback, write_private_file, Profile}, proxy::PROXY_CREDENTIALS_KEY_PREFIX, user_input_generator::TextInputGeneratorTrait};
+
+pub const GITHUB_TOKEN_KEY: &str = "github_token";
+
+/// Somewhere secrets such as the GitHub token can be kept between runs.
+pub trait CredentialStore {
+    fn get(&self, key: &str)-> Result<Option<String>, GittyError>;
+    fn set(&self, key: &str, value: &str)-> Result<(), GittyError>;
+    fn delete(&self, key: &str)-> Result<(), GittyError>;
+    fn describe(&self)-> String;
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CredentialStoreKind {
+    Plaintext,
+    Encrypted,
+}
+
+impl FromStr for CredentialStoreKind {
+    type Err = GittyError;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind.trim().to_lowercase().as_str() {
+            "plaintext" => Ok(CredentialStoreKind::Plaintext),
+            "encrypted" => Ok(CredentialStoreKind::Encrypted),
+            other => Err(GittyError::Config(format!("unknown credential store \"{}\", expected plaintext or encrypted", other))),
+        }
+    }
+}
+
+/// One file per secret, readable only by the current user.
+pub struct PlaintextCredentialStore {
+    dir: PathBuf,
+}
+
+impl PlaintextCredentialStore {
+    pub fn new(dir: PathBuf)-> Self {
+        Self{dir}
+    }
+
+    /// Every secret gitty may have saved here: the GitHub token and any proxy credentials. The directory
+    /// is the profile's, so other files in it, such as the remembered proxy, are not secrets.
+    pub fn keys(&self)-> Result<Vec<String>, GittyError> {
+        let mut keys = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .filter(|key| key.starts_with(PROXY_CREDENTIALS_KEY_PREFIX))
+                .collect(),
+            Err(error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(GittyError::Config(format!("failed to read {}: {}", self.dir.display(), error))),
+        };
+        keys.sort();
+        keys.insert(0, GITHUB_TOKEN_KEY.to_string());
+        Ok(keys)
+    }
+}
+
+impl CredentialStore for PlaintextCredentialStore {
+    fn get(&self, key: &str)-> Result<Option<String>, GittyError> {
+        Ok(read_with_legacy_fallback(&self.dir.join(key), key))
+    }
+
+    fn set(&self, key: &str, value: &str)-> Result<(), GittyError> {
+        write_private_file(&self.dir.join(key), value)
+    }
+
+    fn delete(&self, key: &str)-> Result<(), GittyError> {
+        match fs::remove_file(self.dir.join(key)) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(GittyError::Config(format!("failed to delete {}: {}", key, error))),
+            _ => Ok(()),
+        }
+    }
+
+    fn describe(&self)-> String {
+        format!("plaintext files in {}", self.dir.display())
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize)]
+struct EncryptedFile {
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// All secrets in one file, encrypted with ChaCha20-Poly1305 under a key derived from a passphrase with Argon2id.
+pub struct EncryptedCredentialStore {
+    path: PathBuf,
+    passphrase: String,
+}
+
+impl EncryptedCredentialStore {
+    pub fn new(path: PathBuf, passphrase: String)-> Self {
+        Self{path, passphrase}
+    }
+
+    fn derive_key(&self, salt: &[u8])-> Result<Key, GittyError> {
+        let mut key = Key::default();
+        Argon2::default()
+            .hash_password_into(self.passphrase.as_bytes(), salt, &mut key)
+            .map_err(|error| GittyError::Config(format!("failed to derive encryption key: {}", error)))?;
+        Ok(key)
+    }
+
+    fn read_all(&self)-> Result<BTreeMap<String, String>, GittyError> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(error) => return Err(GittyError::Config(format!("failed to read {}: {}", self.path.display(), error))),
+        };
+        let encrypted_file = serde_json::from_str::<EncryptedFile>(&contents)?;
+        let decode = |value: &str| STANDARD.decode(value)
+            .map_err(|error| GittyError::Deserialization(format!("{} is corrupted: {}", self.path.display(), error)));
+        let salt = decode(&encrypted_file.salt)?;
+        let nonce = decode(&encrypted_file.nonce)?;
+        if nonce.len() != 12 {
+            return Err(GittyError::Deserialization(format!("{} is corrupted: bad nonce", self.path.display())));
+        }
+        let plaintext = ChaCha20Poly1305::new(&self.derive_key(&salt)?)
+            .decrypt(Nonce::from_slice(&nonce), decode(&encrypted_file.ciphertext)?.as_slice())
+            .map_err(|_| GittyError::Auth(format!("wrong passphrase for {}", self.path.display())))?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+
+    fn write_all(&self, credentials: &BTreeMap<String, String>)-> Result<(), GittyError> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = ChaCha20Poly1305::new(&self.derive_key(&salt)?)
+            .encrypt(&nonce, serde_json::to_vec(credentials)?.as_slice())
+            .map_err(|error| GittyError::Config(format!("failed to encrypt credentials: {}", error)))?;
+        let encrypted_file = EncryptedFile {
+            version: 1,
+            salt: STANDARD.encode(salt),
+            nonce: STANDARD.encode(nonce),
+            ciphertext: STANDARD.encode(ciphertext),
+        };
+        write_private_file(&self.path, &serde_json::to_string_pretty(&encrypted_file)?)
+    }
+}
+
+impl CredentialStore for EncryptedCredentialStore {
+    fn get(&self, key: &str)-> Result<Option<String>, GittyError> {
+        Ok(self.read_all()?.remove(key))
+    }
+
+    fn set(&self, key: &str, value: &str)-> Result<(), GittyError> {
+        let mut credentials = self.read_all()?;
+        credentials.insert(key.to_string(), value.to_string());
+        self.write_all(&credentials)
+    }
+
+    fn delete(&self, key: &str)-> Result<(), GittyError> {
+        let mut credentials = self.read_all()?;
+        if credentials.remove(key).is_some() {
+            self.write_all(&credentials)?;
+        }
+        Ok(())
+    }
+
+    fn describe(&self)-> String {
+        format!("encrypted file {}", self.path.display())
+    }
+}
+
+/// `GITTY_PASSPHRASE` if set, otherwise asks for it. New stores ask twice so a typo does not lock the token away.
+pub fn get_passphrase(creating: bool, user_input_generator: &mut dyn TextInputGeneratorTrait)-> Result<String, GittyError> {
+    if let Ok(passphrase) = env::var("GITTY_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    let passphrase = user_input_generator
+        .get_password_input("Please enter the passphrase for your gitty credentials")
+        .filter(|passphrase| !passphrase.is_empty())
+        .ok_or_else(|| GittyError::Auth("no passphrase was entered".to_string()))?;
+    if creating && user_input_generator.get_password_input("Please enter the passphrase again").as_ref() != Some(&passphrase) {
+        return Err(GittyError::Auth("passphrases did not match".to_string()));
+    }
+    Ok(passphrase)
+}
+
+/// Uses the configured kind, or the encrypted store when this profile already has one.
+pub fn open_credential_store(kind: Option<CredentialStoreKind>, profile: &Profile, user_input_generator: &mut dyn TextInputGeneratorTrait)-> Result<Box<dyn CredentialStore>, GittyError> {
+    let encrypted_path = profile.get_encrypted_credentials_path();
+    let kind = kind.unwrap_or(if encrypted_path.exists() { CredentialStoreKind::Encrypted } else { CredentialStoreKind::Plaintext });
+    match kind {
+        CredentialStoreKind::Plaintext => Ok(Box::new(PlaintextCredentialStore::new(profile.get_dir().to_path_buf()))),
+        CredentialStoreKind::Encrypted => {
+            let passphrase = get_passphrase(!encrypted_path.exists(), user_input_generator)?;
+            Ok(Box::new(EncryptedCredentialStore::new(encrypted_path, passphrase)))
+        },
+    }
+}
+
+/// Moves every secret in `keys` out of `from` into `to`, returning the keys that were moved.
+pub fn migrate_credentials(from: &dyn CredentialStore, to: &dyn CredentialStore, keys: &[String])-> Result<Vec<String>, GittyError> {
+    let mut migrated = Vec::new();
+    for key in keys {
+        if let Some(value) = from.get(key)? {
+            to.set(key, &value)?;
+            from.delete(key)?;
+            migrated.push(key.to_string());
+        }
+    }
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use crate::{profile::testing::temporary_profile, user_input_generator::testing::MockTextInputGenerator};
+
+    #[test]
+    fn plaintext_store_round_trip() {
+        let profile = temporary_profile("plaintext_store_round_trip");
+        let store = PlaintextCredentialStore::new(profile.get_dir().to_path_buf());
+
+        assert_eq!(None, store.get("secret").unwrap());
+        store.set("secret", "value").unwrap();
+        assert_eq!(Some("value".to_string()), store.get("secret").unwrap());
+        store.delete("secret").unwrap();
+        assert_eq!(None, store.get("secret").unwrap());
+    }
+
+    #[test]
+    fn encrypted_store_round_trip_without_leaking_secret() {
+        let profile = temporary_profile("encrypted_store_round_trip_without_leaking_secret");
+        let store = EncryptedCredentialStore::new(profile.get_encrypted_credentials_path(), "passphrase".to_string());
+
+        store.set(GITHUB_TOKEN_KEY, "ghp_secret").unwrap();
+
+        assert_eq!(Some("ghp_secret".to_string()), store.get(GITHUB_TOKEN_KEY).unwrap());
+        assert!(!fs::read_to_string(profile.get_encrypted_credentials_path()).unwrap().contains("ghp_secret"));
+    }
+
+    #[test]
+    fn encrypted_store_rejects_wrong_passphrase() {
+        let profile = temporary_profile("encrypted_store_rejects_wrong_passphrase");
+        EncryptedCredentialStore::new(profile.get_encrypted_credentials_path(), "right".to_string())
+            .set(GITHUB_TOKEN_KEY, "ghp_secret").unwrap();
+
+        let result = EncryptedCredentialStore::new(profile.get_encrypted_credentials_path(), "wrong".to_string())
+            .get(GITHUB_TOKEN_KEY);
+
+        assert!(matches!(result, Err(GittyError::Auth(_))));
+    }
+
+    #[test]
+    fn can_migrate_plaintext_token_to_encrypted_store() {
+        let profile = temporary_profile("can_migrate_plaintext_token_to_encrypted_store");
+        let plaintext = PlaintextCredentialStore::new(profile.get_dir().to_path_buf());
+        let encrypted = EncryptedCredentialStore::new(profile.get_encrypted_credentials_path(), "passphrase".to_string());
+        plaintext.set(GITHUB_TOKEN_KEY, "ghp_secret").unwrap();
+
+        let migrated = migrate_credentials(&plaintext, &encrypted, &[GITHUB_TOKEN_KEY.to_string()]).unwrap();
+
+        assert_eq!(vec![GITHUB_TOKEN_KEY.to_string()], migrated);
+        assert_eq!(None, plaintext.get(GITHUB_TOKEN_KEY).unwrap());
+        assert_eq!(Some("ghp_secret".to_string()), encrypted.get(GITHUB_TOKEN_KEY).unwrap());
+    }
+
+    #[test]
+    fn migration_moves_proxy_credentials_too() {
+        let profile = temporary_profile("migration_moves_proxy_credentials_too");
+        let plaintext = PlaintextCredentialStore::new(profile.get_dir().to_path_buf());
+        let encrypted = EncryptedCredentialStore::new(profile.get_encrypted_credentials_path(), "passphrase".to_string());
+        plaintext.set(GITHUB_TOKEN_KEY, "ghp_secret").unwrap();
+        plaintext.set("proxy:proxy.corp:8080", "someone:secret").unwrap();
+        write_private_file(&profile.get_proxy_path(), "http://proxy.corp:8080").unwrap();
+
+        let migrated = migrate_credentials(&plaintext, &encrypted, &plaintext.keys().unwrap()).unwrap();
+
+        assert_eq!(vec![GITHUB_TOKEN_KEY.to_string(), "proxy:proxy.corp:8080".to_string()], migrated);
+        assert_eq!(vec![GITHUB_TOKEN_KEY.to_string()], plaintext.keys().unwrap());
+        assert_eq!(Some("someone:secret".to_string()), encrypted.get("proxy:proxy.corp:8080").unwrap());
+        assert!(profile.get_proxy_path().exists());
+    }
+
+    #[test]
+    fn new_passphrase_must_be_confirmed() {
+        let mut user_input_generator = MockTextInputGenerator::new(vec!["one".to_string(), "two".to_string()]);
+        assert!(matches!(get_passphrase(true, &mut user_input_generator), Err(GittyError::Auth(_))));
+    }
+
+    #[test]
+    fn can_parse_credential_store_kind() {
+        assert_eq!(Ok(CredentialStoreKind::Encrypted), "encrypted".parse::<CredentialStoreKind>());
+        assert!("keychain".parse::<CredentialStoreKind>().is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use std::{thread, time::Duration};
+
+use serde::Deserialize;
+
+use crate::{error::GittyError, http_agent::HttpProxyAgent, remote::RemoteLocation};
+
+/// `repo` is what commenting on commits needs, nothing broader.
+pub const DEFAULT_OAUTH_SCOPES: &str = "repo";
+const DEVICE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+/// Added to the polling interval every time the server answers `slow_down`.
+const SLOW_DOWN_SECONDS: u64 = 5;
+
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+pub struct DeviceCode {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    expires_in: u64,
+    interval: u64,
+}
+
+impl DeviceCode {
+    pub fn get_user_code(&self)-> &str {
+        &self.user_code
+    }
+    pub fn get_verification_uri(&self)-> &str {
+        &self.verification_uri
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Deserialize)]
+#[serde(untagged)]
+enum AccessTokenResponse {
+    Token{access_token: String},
+    Error{error: String, error_description: Option<String>},
+}
+
+/// The OAuth device authorization flow against github.com or a GitHub Enterprise Server.
+pub struct DeviceFlow {
+    base_url: String,
+    client_id: String,
+    scopes: String,
+}
+
+/// Device flow endpoints live on the web host, not the API host, for github.com and GHES alike,
+/// including any port or path prefix the instance is served under.
+pub fn get_device_flow_base_url(remote: &RemoteLocation)-> String {
+    remote.get_web_base_url()
+}
+
+impl DeviceFlow {
+    pub fn new(base_url: &str, client_id: &str, scopes: &str)-> Self {
+        Self{base_url: base_url.trim_end_matches('/').to_string(), client_id: client_id.to_string(), scopes: scopes.to_string()}
+    }
+
+    pub fn request_device_code(&self, http_agent: &HttpProxyAgent)-> Result<DeviceCode, GittyError> {
+        let response = http_agent.post(&format!("{}/login/device/code", self.base_url))
+            .set("Accept", "application/json")
+            .send_form(&[("client_id", &self.client_id), ("scope", &self.scopes)])?;
+        let string_response = response.into_string()
+            .map_err(|error| GittyError::Network(error.to_string()))?;
+        Ok(serde_json::from_str(&string_response)?)
+    }
+
+    /// Polls at the interval the server asked for until the user approves, denies or lets the code expire.
+    pub fn poll_for_token(&self, http_agent: &HttpProxyAgent, device_code: &DeviceCode)-> Result<String, GittyError> {
+        let mut interval = device_code.interval;
+        let mut waited = 0;
+        loop {
+            thread::sleep(Duration::from_secs(interval));
+            waited += interval;
+            let response = http_agent.post(&format!("{}/login/oauth/access_token", self.base_url))
+                .set("Accept", "application/json")
+                .send_form(&[
+                    ("client_id", &self.client_id),
+                    ("device_code", &device_code.device_code),
+                    ("grant_type", DEVICE_GRANT_TYPE),
+                ])?;
+            let string_response = response.into_string()
+                .map_err(|error| GittyError::Network(error.to_string()))?;
+            match serde_json::from_str::<AccessTokenResponse>(&string_response)? {
+                AccessTokenResponse::Token{access_token} => return Ok(access_token),
+                AccessTokenResponse::Error{error, ..} if error == "authorization_pending" && waited < device_code.expires_in => {},
+                AccessTokenResponse::Error{error, ..} if error == "slow_down" => interval += SLOW_DOWN_SECONDS,
+                AccessTokenResponse::Error{error, error_description} => {
+                    return Err(GittyError::Auth(format!("device login failed: {}", error_description.unwrap_or(error))));
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http_agent::testing::serve_responses;
+
+    fn json_response(body: &str)-> String {
+        format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n{}", body)
+    }
+
+    fn device_code_response()-> String {
+        json_response(r#"{"device_code": "the_device_code", "user_code": "ABCD-1234", "verification_uri": "https://github.com/login/device", "expires_in": 900, "interval": 0}"#)
+    }
+
+    #[test]
+    fn device_flow_base_url_is_the_web_host() {
+        assert_eq!("https://github.com", get_device_flow_base_url(&RemoteLocation::parse("git@github.com:bfrazho/gitty.git").unwrap()));
+        assert_eq!("https://github.some-business.com", get_device_flow_base_url(&RemoteLocation::parse("git@github.some-business.com:bfrazho/gitty.git").unwrap()));
+        assert_eq!(
+            "https://github.some-business.com:8443/git",
+            get_device_flow_base_url(&RemoteLocation::parse("https://github.some-business.com:8443/git/bfrazho/gitty.git").unwrap())
+        );
+    }
+
+    #[test]
+    fn can_log_in_with_device_flow() {
+        let (base_url, server) = serve_responses(vec![
+            device_code_response(),
+            json_response(r#"{"error": "authorization_pending"}"#),
+            json_response(r#"{"access_token": "gho_token", "token_type": "bearer", "scope": "repo"}"#),
+        ]);
+        let device_flow = DeviceFlow::new(&base_url, "the_client_id", DEFAULT_OAUTH_SCOPES);
+        let http_agent = HttpProxyAgent::new_with_proxy("localhost:1");
+
+        let device_code = device_flow.request_device_code(&http_agent).unwrap();
+        let token = device_flow.poll_for_token(&http_agent, &device_code).unwrap();
+
+        assert_eq!("ABCD-1234", device_code.get_user_code());
+        assert_eq!("gho_token", token);
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /login/device/code"));
+        assert!(requests[0].contains("client_id=the_client_id&scope=repo"));
+        assert!(requests[2].starts_with("POST /login/oauth/access_token"));
+        assert!(requests[2].contains("device_code=the_device_code"));
+    }
+
+    #[test]
+    fn denied_login_is_an_auth_error() {
+        let (base_url, server) = serve_responses(vec![
+            device_code_response(),
+            json_response(r#"{"error": "access_denied", "error_description": "The authorization request was denied."}"#),
+        ]);
+        let device_flow = DeviceFlow::new(&base_url, "the_client_id", DEFAULT_OAUTH_SCOPES);
+        let http_agent = HttpProxyAgent::new_with_proxy("localhost:1");
+
+        let device_code = device_flow.request_device_code(&http_agent).unwrap();
+        let result = device_flow.poll_for_token(&http_agent, &device_code);
+
+        assert_eq!(Err(GittyError::Auth("device login failed: The authorization request was denied.".to_string())), result);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt::Display;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum GittyError {
+    Auth(String),
+    Network(String),
+    RateLimit(String),
+    Deserialization(String),
+    NotFound(String),
+    Config(String),
+}
+
+impl GittyError {
+    /// Whether waiting and trying again has a chance of succeeding.
+    pub fn is_transient(&self)-> bool {
+        matches!(self, GittyError::Network(_) | GittyError::RateLimit(_))
+    }
+}
+
+impl Display for GittyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GittyError::Auth(message) => write!(f, "authentication failed: {}", message),
+            GittyError::Network(message) => write!(f, "network error: {}", message),
+            GittyError::RateLimit(message) => write!(f, "rate limited: {}", message),
+            GittyError::Deserialization(message) => write!(f, "unexpected response: {}", message),
+            GittyError::NotFound(message) => write!(f, "not found: {}", message),
+            GittyError::Config(message) => write!(f, "configuration error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for GittyError {}
+
+impl From<ureq::Error> for GittyError {
+    fn from(error: ureq::Error) -> Self {
+        match error {
+            ureq::Error::Status(status, response) => {
+                let url = response.get_url().to_string();
+                let rate_limit_exhausted = response.header("x-ratelimit-remaining") == Some("0") || response.header("retry-after").is_some();
+                let body = response.into_string().unwrap_or_default();
+                let rate_limit_exhausted = rate_limit_exhausted || body.to_lowercase().contains("secondary rate limit");
+                let message = format!("{} from {}: {}", status, url, body);
+                match status {
+                    401 => GittyError::Auth(message),
+                    403 if rate_limit_exhausted => GittyError::RateLimit(message),
+                    429 => GittyError::RateLimit(message),
+                    403 => GittyError::Auth(message),
+                    404 => GittyError::NotFound(message),
+                    _ => GittyError::Network(message),
+                }
+            },
+            ureq::Error::Transport(transport) => GittyError::Network(transport.to_string()),
+        }
+    }
+}
+
+impl From<Box<ureq::Error>> for GittyError {
+    fn from(error: Box<ureq::Error>) -> Self {
+        GittyError::from(*error)
+    }
+}
+
+impl From<serde_json::Error> for GittyError {
+    fn from(error: serde_json::Error) -> Self {
+        GittyError::Deserialization(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ureq::Response;
+
+    use super::*;
+
+    fn status_error(status: u16, headers: &str)-> ureq::Error {
+        let response: Response = format!("HTTP/1.1 {} Status\r\n{}\r\nbody", status, headers).parse().unwrap();
+        ureq::Error::Status(status, response)
+    }
+
+    #[test]
+    fn unauthorized_is_an_auth_error() {
+        assert!(matches!(GittyError::from(status_error(401, "")), GittyError::Auth(_)));
+    }
+
+    #[test]
+    fn not_found_is_a_not_found_error() {
+        assert!(matches!(GittyError::from(status_error(404, "")), GittyError::NotFound(_)));
+    }
+
+    #[test]
+    fn exhausted_forbidden_is_a_rate_limit_error() {
+        assert!(matches!(GittyError::from(status_error(403, "x-ratelimit-remaining: 0\r\n")), GittyError::RateLimit(_)));
+        assert!(matches!(GittyError::from(status_error(403, "")), GittyError::Auth(_)));
+        assert!(matches!(GittyError::from(status_error(429, "")), GittyError::RateLimit(_)));
+    }
+
+    #[test]
+    fn secondary_rate_limit_is_a_rate_limit_error() {
+        assert!(matches!(GittyError::from(status_error(403, "retry-after: 60\r\n")), GittyError::RateLimit(_)));
+    }
+
+    #[test]
+    fn server_errors_are_transient() {
+        let error = GittyError::from(status_error(502, ""));
+        assert!(matches!(error, GittyError::Network(_)));
+        assert!(error.is_transient());
+        assert!(!GittyError::Config("bad".to_string()).is_transient());
+    }
+
+    #[test]
+    fn invalid_json_is_a_deserialization_error() {
+        let error = serde_json::from_str::<Vec<String>>("{").unwrap_err();
+        assert!(matches!(GittyError::from(error), GittyError::Deserialization(_)));
+    }
+}
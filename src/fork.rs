@@ -0,0 +1,121 @@
+use serde::Deserialize;
+
+use crate::{error::GittyError, http_agent::HttpProxyAgent, remote::RemoteLocation, repository::GitRepository};
+
+pub const UPSTREAM_REMOTE_NAME: &str = "upstream";
+
+#[derive(Deserialize)]
+struct RepositoryInfo {
+    default_branch: String,
+    fork: bool,
+    parent: Option<ParentRepository>,
+}
+
+#[derive(Deserialize)]
+struct ParentRepository {
+    clone_url: String,
+}
+
+impl GitRepository {
+    //https://docs.github.com/en/rest/repos/repos?apiVersion=2022-11-28#get-a-repository
+    fn get_repository_info(&self, http_agent: &HttpProxyAgent)-> Result<RepositoryInfo, GittyError> {
+        let url = format!("{}/repos/{}/{}", self.get_base_rest_url(), self.get_org_name(), self.get_repository_name());
+        let response = http_agent.get(&url)
+            .set("Authorization",&self.get_bearer_token_string()?)
+            .set("X-GitHub-Api-Version", "2022-11-28")
+            .call()?;
+        let string_response = response.into_string()
+            .map_err(|error| GittyError::Network(error.to_string()))?;
+        Ok(serde_json::from_str(&string_response)?)
+    }
+
+    /// The branch GitHub shows by default, for when no local ref says which one it is.
+    pub fn get_default_branch(&self, http_agent: &HttpProxyAgent)-> Result<String, GittyError> {
+        Ok(self.get_repository_info(http_agent)?.default_branch)
+    }
+
+    /// The repository this one was forked from, or `None` when it is not a fork.
+    pub fn get_fork_parent(&self, http_agent: &HttpProxyAgent)-> Result<Option<RemoteLocation>, GittyError> {
+        match self.get_repository_info(http_agent)? {
+            RepositoryInfo { fork: true, parent: Some(parent), .. } => RemoteLocation::parse(&parent.clone_url).map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn is_same_repository(remote: &RemoteLocation, other: &RemoteLocation)-> bool {
+    remote.get_host() == other.get_host()
+        && remote.get_owner().eq_ignore_ascii_case(other.get_owner())
+        && remote.get_repo().eq_ignore_ascii_case(other.get_repo())
+}
+
+/// Where a fork's upstream commits land: the local remote pointing at `parent`, preferring one named
+/// `upstream`, so its scheme and port are kept. Without one, `parent` as GitHub reported it.
+pub fn choose_upstream(remotes: &[(String, RemoteLocation)], parent: RemoteLocation)-> (Option<String>, RemoteLocation) {
+    let mut matching: Vec<&(String, RemoteLocation)> = remotes.iter()
+        .filter(|(_, remote)| is_same_repository(remote, &parent))
+        .collect();
+    matching.sort_by_key(|(name, _)| name != UPSTREAM_REMOTE_NAME);
+    match matching.first() {
+        Some((name, remote)) => (Some(name.clone()), remote.clone()),
+        None => (None, parent),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http_agent::testing::serve_responses;
+
+    fn remote(url: &str)-> RemoteLocation {
+        RemoteLocation::parse(url).unwrap()
+    }
+
+    #[test]
+    fn prefers_the_remote_pointing_at_the_parent() {
+        let parent = remote("https://github.com/upstream-org/gitty.git");
+        let cases = vec![
+            (vec![("origin", "git@github.com:bfrazho/gitty.git"), ("upstream", "git@github.com:upstream-org/gitty.git")], Some("upstream"), "git@github.com:upstream-org/gitty.git"),
+            (vec![("origin", "git@github.com:bfrazho/gitty.git"), ("team", "git@github.com:Upstream-Org/gitty.git")], Some("team"), "git@github.com:Upstream-Org/gitty.git"),
+            (vec![("mirror", "git@github.com:upstream-org/gitty.git"), ("upstream", "https://github.com/upstream-org/gitty")], Some("upstream"), "https://github.com/upstream-org/gitty"),
+            (vec![("origin", "git@github.com:bfrazho/gitty.git"), ("upstream", "git@github.com:someone-else/gitty.git")], None, "https://github.com/upstream-org/gitty.git"),
+            (vec![("origin", "git@github.some-business.com:upstream-org/gitty.git")], None, "https://github.com/upstream-org/gitty.git"),
+        ];
+        for (remotes, expected_name, expected_url) in cases {
+            let remotes: Vec<(String, RemoteLocation)> = remotes.into_iter().map(|(name, url)| (name.to_string(), remote(url))).collect();
+            assert_eq!(
+                (expected_name.map(str::to_string), remote(expected_url)),
+                choose_upstream(&remotes, parent.clone()),
+                "{:?}", remotes
+            );
+        }
+    }
+
+    #[test]
+    fn finds_the_parent_of_a_fork() {
+        let (base_url, server) = serve_responses(vec![
+            r#"HTTP/1.1 200 OK
+
+{"full_name":"bfrazho/gitty","default_branch":"main","fork":true,"parent":{"full_name":"upstream-org/gitty","clone_url":"https://github.com/upstream-org/gitty.git"}}"#.replace('\n', "\r\n"),
+            "HTTP/1.1 200 OK\r\n\r\n{\"full_name\":\"bfrazho/gitty\",\"default_branch\":\"main\",\"fork\":false}".to_string(),
+        ]);
+        let http_agent = HttpProxyAgent::new_with_proxy("");
+        let repository = GitRepository::new("token".to_string(), remote(&format!("{}/bfrazho/gitty", base_url)), "main".to_string());
+
+        assert_eq!(Some(remote("https://github.com/upstream-org/gitty.git")), repository.get_fork_parent(&http_agent).unwrap());
+        assert_eq!(None, repository.get_fork_parent(&http_agent).unwrap());
+        assert!(server.join().unwrap()[0].starts_with("GET /api/v3/repos/bfrazho/gitty"));
+    }
+
+    #[test]
+    fn finds_the_default_branch() {
+        let (base_url, server) = serve_responses(vec![
+            "HTTP/1.1 200 OK\r\n\r\n{\"full_name\":\"bfrazho/gitty\",\"default_branch\":\"trunk\",\"fork\":false}".to_string(),
+        ]);
+        let http_agent = HttpProxyAgent::new_with_proxy("");
+        let repository = GitRepository::new("token".to_string(), remote(&format!("{}/bfrazho/gitty", base_url)), String::new());
+
+        assert_eq!("trunk", repository.get_default_branch(&http_agent).unwrap());
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,153 @@
+use std::{fs, path::Path, sync::Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde::{Serialize, Deserialize};
+
+use crate::{error::GittyError, http_agent::HttpProxyAgent};
+
+/// Installation tokens last an hour, refresh a little early so a request never goes out with one about to expire.
+const REFRESH_MARGIN_MINUTES: i64 = 5;
+
+#[derive(PartialEq, Eq, Debug, Serialize)]
+struct Claims {
+    iat: i64,
+    exp: i64,
+    iss: String,
+}
+
+impl Claims {
+    /// Backdated a minute for clock drift, and GitHub refuses app JWTs that live longer than ten minutes.
+    fn new(app_id: u64, now: DateTime<Utc>)-> Self {
+        Self{
+            iat: (now - Duration::seconds(60)).timestamp(),
+            exp: (now + Duration::minutes(9)).timestamp(),
+            iss: app_id.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Installation {
+    id: u64,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Deserialize)]
+struct InstallationToken {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl InstallationToken {
+    fn needs_refresh(&self, now: DateTime<Utc>)-> bool {
+        self.expires_at - now <= Duration::minutes(REFRESH_MARGIN_MINUTES)
+    }
+}
+
+/// Authenticates as a GitHub App installation, swapping the app's JWT for installation tokens as they run out.
+pub struct GitHubAppAuth {
+    app_id: u64,
+    private_key: EncodingKey,
+    http_agent: HttpProxyAgent,
+    installation_id: Mutex<Option<u64>>,
+    installation_token: Mutex<Option<InstallationToken>>,
+}
+
+impl GitHubAppAuth {
+    pub fn new(app_id: u64, private_key_pem: &[u8], http_agent: HttpProxyAgent)-> Result<Self, GittyError> {
+        let private_key = EncodingKey::from_rsa_pem(private_key_pem)
+            .map_err(|error| GittyError::Config(format!("invalid GitHub App private key: {}", error)))?;
+        Ok(Self{app_id, private_key, http_agent, installation_id: Mutex::new(None), installation_token: Mutex::new(None)})
+    }
+
+    pub fn from_private_key_file(app_id: u64, private_key_path: &Path, http_agent: HttpProxyAgent)-> Result<Self, GittyError> {
+        let private_key_pem = fs::read(private_key_path)
+            .map_err(|error| GittyError::Config(format!("failed to read {}: {}", private_key_path.display(), error)))?;
+        Self::new(app_id, &private_key_pem, http_agent)
+    }
+
+    /// The same app with nothing cached, for a repository the app may be installed on separately.
+    pub fn for_another_repository(&self)-> Self {
+        Self{
+            app_id: self.app_id,
+            private_key: self.private_key.clone(),
+            http_agent: self.http_agent.clone(),
+            installation_id: Mutex::new(None),
+            installation_token: Mutex::new(None),
+        }
+    }
+
+    fn create_jwt(&self, now: DateTime<Utc>)-> Result<String, GittyError> {
+        encode(&Header::new(Algorithm::RS256), &Claims::new(self.app_id, now), &self.private_key)
+            .map_err(|error| GittyError::Auth(format!("failed to sign GitHub App JWT: {}", error)))
+    }
+
+    fn get_installation_id(&self, jwt: &str, base_rest_url: &str, owner: &str, repository: &str)-> Result<u64, GittyError> {
+        let mut installation_id = self.installation_id.lock().unwrap();
+        if let Some(installation_id) = *installation_id {
+            return Ok(installation_id);
+        }
+        let response = self.http_agent.get(&format!("{}/repos/{}/{}/installation", base_rest_url, owner, repository))
+            .set("Authorization", &format!("Bearer {}", jwt))
+            .set("X-GitHub-Api-Version", "2022-11-28")
+            .call()?;
+        let string_response = response.into_string()
+            .map_err(|error| GittyError::Network(error.to_string()))?;
+        let id = serde_json::from_str::<Installation>(&string_response)?.id;
+        *installation_id = Some(id);
+        Ok(id)
+    }
+
+    /// A valid installation token for the repository, only calling GitHub when the cached one is close to expiring.
+    pub fn get_installation_token(&self, base_rest_url: &str, owner: &str, repository: &str)-> Result<String, GittyError> {
+        let now = Utc::now();
+        let mut installation_token = self.installation_token.lock().unwrap();
+        if let Some(installation_token) = installation_token.as_ref().filter(|token| !token.needs_refresh(now)) {
+            return Ok(installation_token.token.clone());
+        }
+        let jwt = self.create_jwt(now)?;
+        let installation_id = self.get_installation_id(&jwt, base_rest_url, owner, repository)?;
+        let response = self.http_agent.post(&format!("{}/app/installations/{}/access_tokens", base_rest_url, installation_id))
+            .set("Authorization", &format!("Bearer {}", jwt))
+            .set("X-GitHub-Api-Version", "2022-11-28")
+            .send_string("")?;
+        let string_response = response.into_string()
+            .map_err(|error| GittyError::Network(error.to_string()))?;
+        let new_token = serde_json::from_str::<InstallationToken>(&string_response)?;
+        let token = new_token.token.clone();
+        *installation_token = Some(new_token);
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn jwt_claims_are_backdated_and_short_lived() {
+        let now = Utc.with_ymd_and_hms(2023, 4, 1, 12, 0, 0).unwrap();
+        let claims = Claims::new(1234, now);
+
+        assert_eq!("1234", claims.iss);
+        assert_eq!(now.timestamp() - 60, claims.iat);
+        assert!(claims.exp - claims.iat <= 600);
+    }
+
+    #[test]
+    fn installation_token_is_refreshed_before_it_expires() {
+        let token: InstallationToken = serde_json::from_str(r#"{"token": "ghs_token", "expires_at": "2023-04-01T13:00:00Z"}"#).unwrap();
+
+        assert!(!token.needs_refresh(Utc.with_ymd_and_hms(2023, 4, 1, 12, 0, 0).unwrap()));
+        assert!(token.needs_refresh(Utc.with_ymd_and_hms(2023, 4, 1, 12, 56, 0).unwrap()));
+        assert!(token.needs_refresh(Utc.with_ymd_and_hms(2023, 4, 1, 14, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn invalid_private_key_is_a_config_error() {
+        let result = GitHubAppAuth::new(1234, b"not a key", HttpProxyAgent::new_with_proxy("localhost:1"));
+        assert!(matches!(result, Err(GittyError::Config(_))));
+    }
+}
@@ -1,194 +1,706 @@
-use std::{
-    env::current_exe,
-    fs,
-    path::PathBuf,
-    sync::{atomic::AtomicBool, Arc},
-};
+use std::{collections::BTreeMap, sync::{atomic::{AtomicBool, Ordering}, Arc}, time::Instant};
 
-use ureq::{Agent, AgentBuilder, Error, Proxy, Request, Response};
+use chrono::Utc;
+use ureq::{Agent, AgentBuilder, Error, ErrorKind, Proxy, Request, Response};
 
-use crate::user_input_generator::TextInputGeneratorTrait;
+use crate::{config::Config, credential_store::CredentialStore, error::GittyError, http_cache::{buffer_response, CachedResponse, HttpCache}, http_trace::HttpTrace, profile::{read_with_legacy_fallback, write_private_file, Profile}, rate_limit::{RateLimitBudget, RateLimitGovernor}, retry::{is_retryable, RetryPolicy}, proxy::{add_proxy_credentials, get_proxy_credentials_key, parse_url_scheme_and_host, ProxySettings}, tls::{build_tls_config, find_tls_settings_key, TlsSettings}, user_input_generator::TextInputGeneratorTrait};
+
+/// Which way requests that could use a proxy go, shared by every request through the same proxy.
+#[derive(Default)]
+struct Route {
+    use_proxy: AtomicBool,
+    reported: AtomicBool,
+}
+
+impl Route {
+    fn new(use_proxy: bool)-> Self {
+        Self{use_proxy: AtomicBool::new(use_proxy), reported: AtomicBool::new(false)}
+    }
+}
+
+/// What sending a request gives back. `ureq::Error` carries a whole response, so it is boxed to keep results small.
+pub type HttpResult<T = Response> = Result<T, Box<Error>>;
 
 pub struct HttpRequest {
-    use_proxy: Arc<AtomicBool>,
+    rate_limit: RateLimitGovernor,
+    retry_policy: RetryPolicy,
+    idempotent: Option<bool>,
+    cache: Option<HttpCache>,
+    conditional: bool,
+    trace: Option<HttpTrace>,
+    trace_body: Option<String>,
+    proxy: Option<String>,
+    route: Arc<Route>,
     non_proxy_request: Request,
-    proxy_request: Request,
+    proxy_request: Option<Request>,
 }
 
 impl HttpRequest {
     pub fn set(mut self, header: &str, value: &str) -> Self {
-        self.proxy_request = self.proxy_request.set(header, value);
+        self.proxy_request = self.proxy_request.map(|request| request.set(header, value));
         self.non_proxy_request = self.non_proxy_request.set(header, value);
         self
     }
 
     pub fn query(mut self, header: &str, value: &str) -> Self {
-        self.proxy_request = self.proxy_request.query(header, value);
+        self.proxy_request = self.proxy_request.map(|request| request.query(header, value));
         self.non_proxy_request = self.non_proxy_request.query(header, value);
         self
     }
 
     pub fn get_active_request(&self, use_proxy: bool) -> &Request {
-        if use_proxy {
-            &self.proxy_request
-        } else {
-            &self.non_proxy_request
+        match &self.proxy_request {
+            Some(proxy_request) if use_proxy => proxy_request,
+            _ => &self.non_proxy_request,
+        }
+    }
+
+    /// Marks a request as safe to send twice, e.g. a GraphQL query, which is a POST that only reads.
+    pub fn idempotent(mut self, idempotent: bool) -> Self {
+        self.idempotent = Some(idempotent);
+        self
+    }
+
+    /// Revalidates this GET against the on-disk cache, so an unchanged resource comes back as a 304
+    /// that GitHub does not count against the rate limit.
+    pub fn conditional(mut self) -> Self {
+        self.conditional = self.non_proxy_request.method() == "GET";
+        self
+    }
+
+    /// What the cache holds for this request, without sending anything.
+    pub fn cached(&self) -> Option<CachedResponse> {
+        self.cache.as_ref()?.get(self.non_proxy_request.url())
+    }
+
+    /// POST and PATCH may have taken effect once the server has seen them, so they are not sent twice.
+    fn is_idempotent(&self) -> bool {
+        self.idempotent.unwrap_or_else(|| !matches!(self.non_proxy_request.method(), "POST" | "PATCH"))
+    }
+
+    fn send_on_route(&self, send: &impl Fn(Request) -> HttpResult, use_proxy: bool) -> HttpResult {
+        let request = self.get_active_request(use_proxy).clone();
+        let Some(trace) = &self.trace else {
+            return send(request)
+        };
+        let route = match &self.proxy {
+            Some(proxy) if use_proxy => format!("through {}", proxy),
+            _ => describe_route(false).to_string(),
+        };
+        let started_at = Instant::now();
+        let result = send(request.clone());
+        trace.record(&request, &route, self.trace_body.as_deref(), result, started_at.elapsed())
+    }
+
+    /// Tries the other route only when this one failed in transport: always if the request never left,
+    /// and only for idempotent requests if the server may already have received it.
+    fn send_with_fallback(&mut self, send: impl Fn(Request) -> HttpResult) -> HttpResult {
+        let use_proxy = self.route.use_proxy.load(Ordering::Relaxed);
+        match self.send_on_route(&send, use_proxy) {
+            Err(error) if self.proxy_request.is_some() && can_retry_on_other_route(&error, self.is_idempotent()) => {
+                let result = self.send_on_route(&send, !use_proxy);
+                if reached_server(&result) {
+                    self.route.use_proxy.store(!use_proxy, Ordering::Relaxed);
+                    self.route.reported.store(true, Ordering::Relaxed);
+                    println!(
+                        "Reached {} {} after going {} failed: {}",
+                        self.non_proxy_request.url(), describe_route(!use_proxy), describe_route(use_proxy), error
+                    );
+                }
+                result
+            },
+            result => {
+                if self.proxy_request.is_some() && reached_server(&result) && !self.route.reported.swap(true, Ordering::Relaxed) {
+                    println!("Reached {} {}", self.non_proxy_request.url(), describe_route(use_proxy));
+                }
+                result
+            },
         }
     }
 
-    pub fn send_string(&mut self, data: &str) -> Result<Response, Error> {
-        let use_proxy = self.use_proxy.load(std::sync::atomic::Ordering::Relaxed);
-        let result = self.get_active_request(use_proxy).clone().send_string(data);
-        if result.is_ok() {
-            result
-        } else {
-            self.use_proxy
-                .store(!use_proxy, std::sync::atomic::Ordering::Relaxed);
-            self.get_active_request(!use_proxy)
-                .clone()
-                .send_string(data)
+    /// Waits out any rate limit before sending and records the budget GitHub reports back.
+    fn send_within_rate_limit(&mut self, send: impl Fn(Request) -> HttpResult) -> HttpResult {
+        let url = self.non_proxy_request.url().to_string();
+        self.rate_limit.wait_for_budget(&url);
+        match self.send_with_fallback(send) {
+            Err(error) => match *error {
+                Error::Status(403, response) => {
+                    let (response, body) = buffer_response(response)?;
+                    self.rate_limit.record_response(&url, &response, Some(&body), Utc::now());
+                    Err(Box::new(Error::Status(403, response)))
+                },
+                Error::Status(status, response) => {
+                    self.rate_limit.record_response(&url, &response, None, Utc::now());
+                    Err(Box::new(Error::Status(status, response)))
+                },
+                error => Err(Box::new(error)),
+            },
+            Ok(response) => {
+                self.rate_limit.record_response(&url, &response, None, Utc::now());
+                Ok(response)
+            },
         }
     }
-    pub fn call(&mut self) -> Result<Response, Error> {
-        let use_proxy = self.use_proxy.load(std::sync::atomic::Ordering::Relaxed);
-        let result = self.get_active_request(use_proxy).clone().call();
-        if result.is_ok() {
-            result
-        } else {
-            self.use_proxy
-                .store(!use_proxy, std::sync::atomic::Ordering::Relaxed);
-            self.get_active_request(!use_proxy).clone().call()
+
+    /// Sends again after transient failures, as often and as patiently as the retry policy allows.
+    fn send_with_retries(&mut self, send: impl Fn(Request) -> HttpResult) -> HttpResult {
+        let mut attempt = 1;
+        loop {
+            match self.send_within_rate_limit(&send) {
+                Err(error) if attempt < self.retry_policy.get_max_attempts() && is_retryable(&error, self.is_idempotent()) => {
+                    let delay = self.retry_policy.get_random_delay(attempt);
+                    println!(
+                        "{} {} failed: {}, retrying in {}ms ({}/{})",
+                        self.non_proxy_request.method(), self.non_proxy_request.url(), error, delay.as_millis(), attempt, self.retry_policy.get_max_attempts()
+                    );
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+
+    pub fn send_string(&mut self, data: &str) -> HttpResult {
+        self.trace_body = Some(data.to_string());
+        self.send_with_retries(|request| request.send_string(data).map_err(Box::new))
+    }
+    pub fn send_form(&mut self, data: &[(&str, &str)]) -> HttpResult {
+        self.trace_body = Some(data.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<String>>().join("&"));
+        self.send_with_retries(|request| request.send_form(data).map_err(Box::new))
+    }
+    pub fn call(&mut self) -> HttpResult {
+        let Some(cache) = self.cache.clone().filter(|_| self.conditional) else {
+            return self.send_with_retries(|request| request.call().map_err(Box::new))
+        };
+        let cached = self.cached();
+        let response = self.send_with_retries(|request| match &cached {
+            Some(cached) => cached.add_validators(request).call(),
+            None => request.call(),
+        }.map_err(Box::new))?;
+        match cached {
+            Some(cached) if response.status() == 304 => cached.to_response(),
+            _ if response.status() != 200 => Ok(response),
+            _ => {
+                let fresh = CachedResponse::from_response(self.non_proxy_request.url(), response, Utc::now())?;
+                if fresh.has_validators() {
+                    if let Err(error) = cache.put(&fresh) {
+                        eprintln!("Failed to cache {}: {}", self.non_proxy_request.url(), error);
+                    }
+                }
+                fresh.to_response()
+            },
         }
     }
 }
 
+/// Any HTTP response, error status or not, shows the route works.
+fn reached_server(result: &HttpResult) -> bool {
+    !result.as_ref().is_err_and(|error| matches!(**error, Error::Transport(_)))
+}
+
+fn describe_route(use_proxy: bool) -> &'static str {
+    if use_proxy {
+        "through the proxy"
+    } else {
+        "directly"
+    }
+}
+
+/// HTTP errors mean the route works and are never retried. Connection-level failures happen before
+/// anything is sent, while I/O and parse errors can happen after the server already acted on the request.
+fn can_retry_on_other_route(error: &Error, idempotent: bool) -> bool {
+    match error {
+        Error::Status(_, _) => false,
+        Error::Transport(transport) => match transport.kind() {
+            ErrorKind::Dns
+            | ErrorKind::ConnectionFailed
+            | ErrorKind::InvalidProxyUrl
+            | ErrorKind::ProxyConnect
+            | ErrorKind::ProxyUnauthorized => true,
+            ErrorKind::Io | ErrorKind::BadStatus | ErrorKind::BadHeader => idempotent,
+            _ => false,
+        },
+    }
+}
+
+fn build_agent(proxy: Option<&str>, tls_config: Option<&Arc<rustls::ClientConfig>>) -> Result<Agent, GittyError> {
+    let mut builder = AgentBuilder::new();
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(Proxy::new(proxy)
+            .map_err(|error| GittyError::Config(format!("invalid proxy \"{}\": {}", proxy, error)))?);
+    }
+    if let Some(tls_config) = tls_config {
+        builder = builder.tls_config(tls_config.clone());
+    }
+    Ok(builder.build())
+}
+
+/// Sends each request through the proxy configured for its host, falling back to going directly (the other
+/// way round for a remembered proxy, see `ProxySettings::trying_direct_first`), with the CAs and client
+/// certificate configured for that host.
+#[derive(Clone)]
 pub struct HttpProxyAgent {
-    use_proxy: Arc<AtomicBool>,
-    non_proxy_agent: Agent,
-    proxy_agent: Agent,
+    proxy_settings: ProxySettings,
+    tls_settings: BTreeMap<String, TlsSettings>,
+    /// One agent per proxy (or none) and `[tls]` key (or none) so each keeps its own connection pool.
+    agents: BTreeMap<(Option<String>, Option<String>), Agent>,
+    routes: BTreeMap<String, Arc<Route>>,
+    rate_limit: RateLimitGovernor,
+    retry_policy: RetryPolicy,
+    cache: Option<HttpCache>,
+    trace: Option<HttpTrace>,
 }
 
 impl HttpProxyAgent {
-    pub fn new(user_input_generator: &mut dyn TextInputGeneratorTrait) -> Self {
-        let proxy = get_proxy_and_prompt_if_not_found(user_input_generator);
-        HttpProxyAgent {
-            use_proxy: Arc::new(AtomicBool::new(false)),
-            non_proxy_agent: Agent::new(),
-            proxy_agent: AgentBuilder::new()
-                .proxy(Proxy::new(proxy).unwrap())
-                .build(),
-        }
+    pub fn new(proxy_settings: ProxySettings, tls_settings: BTreeMap<String, TlsSettings>, credential_store: &dyn CredentialStore) -> Result<Self, GittyError> {
+        Self::build(proxy_settings, tls_settings, |key| credential_store.get(key))
     }
 
+    #[cfg(test)]
     pub fn new_with_proxy(proxy: &str) -> Self {
-        HttpProxyAgent {
-            use_proxy: Arc::new(AtomicBool::new(false)),
-            non_proxy_agent: Agent::new(),
-            proxy_agent: AgentBuilder::new()
-                .proxy(Proxy::new(proxy).unwrap())
-                .build(),
-        }
+        Self::build(ProxySettings::default().with_default_proxy(proxy).trying_direct_first(), BTreeMap::new(), |_| Ok(None)).unwrap()
     }
 
-    pub fn get(&self, path: &str) -> HttpRequest {
-        HttpRequest {
-            use_proxy: self.use_proxy.clone(),
-            proxy_request: self.proxy_agent.get(path),
-            non_proxy_request: self.non_proxy_agent.get(path),
+    fn build(
+        proxy_settings: ProxySettings,
+        tls_settings: BTreeMap<String, TlsSettings>,
+        get_credentials: impl Fn(&str) -> Result<Option<String>, GittyError>,
+    ) -> Result<Self, GittyError> {
+        let mut proxies = vec![(None, None)];
+        for proxy in proxy_settings.get_proxies() {
+            let credentials = get_credentials(&get_proxy_credentials_key(proxy))?;
+            proxies.push((Some(proxy.to_string()), Some(add_proxy_credentials(proxy, credentials.as_deref())?)));
+        }
+        let mut tls_configs = vec![(None, None)];
+        for (tls_key, settings) in &tls_settings {
+            tls_configs.push((Some(tls_key.clone()), Some(build_tls_config(settings)?)));
         }
+        let mut agents = BTreeMap::new();
+        for (proxy, proxy_url) in &proxies {
+            for (tls_key, tls_config) in &tls_configs {
+                agents.insert((proxy.clone(), tls_key.clone()), build_agent(proxy_url.as_deref(), tls_config.as_ref())?);
+            }
+        }
+        let routes = proxies.into_iter()
+            .filter_map(|(proxy, _)| proxy)
+            .map(|proxy| (proxy, Arc::new(Route::new(!proxy_settings.is_direct_first()))))
+            .collect();
+        Ok(HttpProxyAgent {
+            proxy_settings,
+            tls_settings,
+            agents,
+            routes,
+            rate_limit: RateLimitGovernor::default(),
+            retry_policy: RetryPolicy::default(),
+            cache: None,
+            trace: None,
+        })
     }
 
-    pub fn post(&self, path: &str) -> HttpRequest {
-        HttpRequest {
-            use_proxy: self.use_proxy.clone(),
-            proxy_request: self.proxy_agent.post(path),
-            non_proxy_request: self.non_proxy_agent.post(path),
-        }
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
     }
 
-    pub fn put(&self, path: &str) -> HttpRequest {
-        HttpRequest {
-            use_proxy: self.use_proxy.clone(),
-            proxy_request: self.proxy_agent.put(path),
-            non_proxy_request: self.non_proxy_agent.put(path),
-        }
+    pub fn with_cache(mut self, cache: HttpCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn with_trace(mut self, trace: HttpTrace) -> Self {
+        self.trace = Some(trace);
+        self
     }
 
-    pub fn delete(&self, path: &str) -> HttpRequest {
-        HttpRequest {
-            use_proxy: self.use_proxy.clone(),
-            proxy_request: self.proxy_agent.delete(path),
-            non_proxy_request: self.non_proxy_agent.delete(path),
+    pub fn get_retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    fn request(&self, method: &str, path: &str) -> HttpRequest {
+        let host = parse_url_scheme_and_host(path).map(|(_, host)| host).unwrap_or_default();
+        let tls_key = find_tls_settings_key(&self.tls_settings, host).map(|tls_key| tls_key.to_string());
+        let proxy = self.proxy_settings.get_proxy_for_url(path).map(|proxy| proxy.to_string());
+        let timeout = self.retry_policy.get_timeout();
+        let non_proxy_request = self.agents[&(None, tls_key.clone())].request(method, path).timeout(timeout);
+        match proxy {
+            Some(proxy) => HttpRequest {
+                rate_limit: self.rate_limit.clone(),
+                retry_policy: self.retry_policy.clone(),
+                idempotent: None,
+                cache: self.cache.clone(),
+                conditional: false,
+                trace: self.trace.clone(),
+                trace_body: None,
+                route: self.routes[&proxy].clone(),
+                proxy_request: Some(self.agents[&(Some(proxy.clone()), tls_key)].request(method, path).timeout(timeout)),
+                proxy: Some(proxy),
+                non_proxy_request,
+            },
+            None => HttpRequest {
+                rate_limit: self.rate_limit.clone(),
+                retry_policy: self.retry_policy.clone(),
+                idempotent: None,
+                cache: self.cache.clone(),
+                conditional: false,
+                trace: self.trace.clone(),
+                trace_body: None,
+                route: Arc::default(),
+                proxy_request: None,
+                proxy: None,
+                non_proxy_request,
+            },
         }
     }
+
+    pub fn get_rate_limits(&self) -> BTreeMap<String, RateLimitBudget> {
+        self.rate_limit.get_budgets()
+    }
+
+    pub fn get(&self, path: &str) -> HttpRequest {
+        self.request("GET", path)
+    }
+
+    pub fn post(&self, path: &str) -> HttpRequest {
+        self.request("POST", path)
+    }
 }
 
-fn get_proxy_path() -> PathBuf {
-    let mut path_to_github_token = current_exe().unwrap().parent().unwrap().to_owned();
-    path_to_github_token.push("proxy");
-    path_to_github_token
+/// A `proxy` setting wins over the standard environment variables. Only when neither they nor any
+/// per-host rule configure a proxy is the proxy saved with `gitty proxy set` used, and without one every request goes direct.
+pub fn load_proxy_settings(
+    config: &Config,
+    profile: &Profile,
+    get_var: impl Fn(&str) -> Option<String>,
+) -> ProxySettings {
+    let proxy_settings = ProxySettings::from_env(get_var).with_rules(config.get_proxy_rules());
+    match config.get_proxy() {
+        Some(proxy) => proxy_settings.with_default_proxy(proxy),
+        None if proxy_settings.is_configured() => proxy_settings,
+        None => match read_with_legacy_fallback(&profile.get_proxy_path(), "proxy") {
+            Some(proxy) => proxy_settings.with_default_proxy(&proxy).trying_direct_first(),
+            None => proxy_settings,
+        },
+    }
 }
 
-fn get_proxy_and_prompt_if_not_found(
+/// Asks for the proxy to fall back to and saves it to the profile, a blank answer meaning always connect directly.
+pub fn prompt_for_proxy(
+    profile: &Profile,
     user_input_generator: &mut dyn TextInputGeneratorTrait,
-) -> String {
-    let path_to_github_token = get_proxy_path();
-    match fs::read_to_string(path_to_github_token.clone()) {
-        Ok(github_token) => github_token,
-        Err(_) => {
-            let github_token = user_input_generator
-                .get_text_input("Please enter your proxy, leave blank if you don't have one")
-                .unwrap();
-            fs::write(path_to_github_token, github_token.clone())
-                .expect("failed to write proxy to file");
-            github_token
-        }
+) -> Result<String, GittyError> {
+    let proxy = user_input_generator
+        .get_text_input("Please enter your proxy, leave blank to connect directly")
+        .ok_or_else(|| GittyError::Config("no proxy answer was given".to_string()))?;
+    write_private_file(&profile.get_proxy_path(), &proxy)?;
+    Ok(proxy)
+}
+
+#[cfg(test)]
+pub mod testing {
+    use std::{io::{BufRead, BufReader, Read, Write}, net::TcpListener, thread::{self, JoinHandle}};
+
+    /// A stand-in HTTP server on localhost that answers each connection with the next canned response,
+    /// e.g. `"HTTP/1.1 200 OK\r\n\r\n{}"`, and hands back the raw requests it received once they have all been served.
+    pub fn serve_responses(responses: Vec<String>)-> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            responses.into_iter().map(|response| {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                    request.push_str(&line);
+                    if line == "\r\n" || line.is_empty() {
+                        break;
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                request.push_str(&String::from_utf8_lossy(&body));
+                let response = response.replacen("\r\n", "\r\nConnection: close\r\n", 1);
+                stream.write_all(response.as_bytes()).unwrap();
+                request
+            }).collect()
+        });
+        (base_url, handle)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::fs;
-
-    use serial_test::serial;
+    use std::{fs, path::PathBuf, time::Duration};
 
     use super::*;
-    use crate::user_input_generator::testing::MockTextInputGenerator;
+    use super::testing::serve_responses;
+    use crate::http_cache::testing::temporary_cache;
+    use crate::{config::ConfigLayer, credential_store::PlaintextCredentialStore, profile::testing::temporary_profile, user_input_generator::testing::MockTextInputGenerator};
 
     #[test]
-    #[serial]
-    fn handle_when_proxy_not_saved() {
-        let path_to_github_token = get_proxy_path();
-        fs::remove_file(path_to_github_token.clone()).unwrap_or_default();
+    fn prompted_proxy_is_saved() {
+        let profile = temporary_profile("prompted_proxy_is_saved");
 
         let mut user_input_generator = MockTextInputGenerator::new(vec!["proxy".to_string()]);
 
-        let github_token = get_proxy_and_prompt_if_not_found(&mut user_input_generator);
+        let proxy = prompt_for_proxy(&profile, &mut user_input_generator).unwrap();
 
-        assert_eq!("proxy".to_string(), github_token);
+        assert_eq!("proxy".to_string(), proxy);
         assert_eq!(
             "proxy",
-            fs::read_to_string(path_to_github_token.clone()).unwrap()
+            fs::read_to_string(profile.get_proxy_path()).unwrap()
         );
-        fs::remove_file(path_to_github_token).expect("failed to delete token");
     }
 
     #[test]
-    #[serial]
-    fn handle_when_token_already_exists() {
-        let path_to_github_token = get_proxy_path();
-        fs::remove_file(path_to_github_token.clone()).unwrap_or_default();
-        fs::write(path_to_github_token.clone(), "existing_github_token")
-            .expect("failed to create token");
-        let mut user_input_generator = MockTextInputGenerator::new(Vec::new());
+    fn saved_proxy_is_used_when_nothing_else_is_configured() {
+        let profile = temporary_profile("saved_proxy_is_used_when_nothing_else_is_configured");
+        write_private_file(&profile.get_proxy_path(), "http://existing.proxy:8080")
+            .expect("failed to create proxy");
+        let config = Config::resolve(PathBuf::from("."), ConfigLayer::default(), Vec::new()).unwrap();
 
-        let github_token = get_proxy_and_prompt_if_not_found(&mut user_input_generator);
+        let proxy_settings = load_proxy_settings(&config, &profile, |_| None);
 
-        assert_eq!("existing_github_token".to_string(), github_token);
-        assert_eq!(
-            "existing_github_token",
-            fs::read_to_string(path_to_github_token.clone()).unwrap()
-        );
-        fs::remove_file(path_to_github_token).expect("failed to delete token");
+        assert_eq!(Some("http://existing.proxy:8080"), proxy_settings.get_proxy_for_url("https://api.github.com/user"));
+        assert!(proxy_settings.is_direct_first());
+    }
+
+    #[test]
+    fn without_any_proxy_configured_nothing_is_asked_or_saved() {
+        let profile = temporary_profile("without_any_proxy_configured_nothing_is_asked_or_saved");
+        let config = Config::resolve(PathBuf::from("."), ConfigLayer::default(), Vec::new()).unwrap();
+
+        let proxy_settings = load_proxy_settings(&config, &profile, |_| None);
+
+        assert_eq!(None, proxy_settings.get_proxy_for_url("https://api.github.com/user"));
+        assert!(!profile.get_proxy_path().exists());
+    }
+
+    fn closed_port()-> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    fn is_using_proxy(http_agent: &HttpProxyAgent)-> bool {
+        http_agent.routes.values().any(|route| route.use_proxy.load(Ordering::Relaxed))
+    }
+
+    #[test]
+    fn http_errors_do_not_switch_route() {
+        let (base_url, server) = serve_responses(vec!["HTTP/1.1 404 Not Found\r\n\r\n".to_string()]);
+        let http_agent = HttpProxyAgent::new_with_proxy(&closed_port());
+
+        let result = http_agent.post(&format!("{}/comments", base_url)).send_string("{}");
+
+        assert!(matches!(result.map_err(|error| *error), Err(Error::Status(404, _))));
+        assert!(!is_using_proxy(&http_agent));
+        assert_eq!(1, server.join().unwrap().len());
+    }
+
+    #[test]
+    fn falls_back_to_proxy_when_host_cannot_be_reached_directly() {
+        let (proxy_url, server) = serve_responses(vec!["HTTP/1.1 200 OK\r\n\r\nok".to_string()]);
+        let http_agent = HttpProxyAgent::new_with_proxy(&proxy_url);
+
+        let response = http_agent.post("http://gitty.invalid/comments").send_string("{}").unwrap();
+
+        assert_eq!("ok", response.into_string().unwrap());
+        assert!(is_using_proxy(&http_agent));
+        assert!(server.join().unwrap()[0].starts_with("POST http://gitty.invalid/comments"));
+    }
+
+    #[test]
+    fn configured_proxy_is_tried_before_going_directly() {
+        let (direct_url, _) = serve_responses(vec!["HTTP/1.1 200 OK\r\n\r\ndirect".to_string()]);
+        let (proxy_url, proxy) = serve_responses(vec!["HTTP/1.1 200 OK\r\n\r\nproxy".to_string()]);
+        let http_agent = HttpProxyAgent::build(ProxySettings::default().with_default_proxy(&proxy_url), BTreeMap::new(), |_| Ok(None)).unwrap();
+
+        let response = http_agent.get(&format!("{}/user", direct_url)).call().unwrap();
+
+        assert_eq!("proxy", response.into_string().unwrap());
+        assert!(is_using_proxy(&http_agent));
+        assert!(proxy.join().unwrap()[0].starts_with(&format!("GET {}/user", direct_url)));
+    }
+
+    #[test]
+    fn posts_are_not_resent_after_the_server_received_them() {
+        let (base_url, server) = serve_responses(vec![String::new()]);
+        let http_agent = HttpProxyAgent::new_with_proxy(&closed_port());
+
+        let result = http_agent.post(&format!("{}/comments", base_url)).send_string("{}");
+
+        assert!(matches!(result.as_ref().map_err(|error| &**error), Err(Error::Transport(transport)) if transport.kind() != ErrorKind::ProxyConnect));
+        assert!(!is_using_proxy(&http_agent));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn only_transport_failures_are_retried() {
+        let status_error = Error::Status(401, "HTTP/1.1 401 Unauthorized\r\n\r\n".parse().unwrap());
+        assert!(!can_retry_on_other_route(&status_error, true));
+    }
+
+    #[test]
+    fn without_a_proxy_requests_only_go_direct() {
+        let http_agent = HttpProxyAgent::new_with_proxy("");
+
+        let request = http_agent.get("https://api.github.com/user");
+
+        assert!(http_agent.routes.is_empty());
+        assert!(request.proxy_request.is_none());
+    }
+
+    #[test]
+    fn proxy_credentials_come_from_the_credential_store() {
+        let profile = temporary_profile("proxy_credentials_come_from_the_credential_store");
+        let credential_store = PlaintextCredentialStore::new(profile.get_dir().to_path_buf());
+        credential_store.set(&get_proxy_credentials_key("http://proxy.corp:8080"), "someone:secret").unwrap();
+
+        let http_agent = HttpProxyAgent::new(ProxySettings::default().with_default_proxy("http://proxy.corp:8080"), BTreeMap::new(), &credential_store).unwrap();
+
+        assert_eq!(vec!["http://proxy.corp:8080"], http_agent.routes.keys().collect::<Vec<&String>>());
+    }
+
+    #[test]
+    fn environment_proxy_wins_over_the_saved_proxy() {
+        let profile = temporary_profile("environment_proxy_wins_over_the_saved_proxy");
+        write_private_file(&profile.get_proxy_path(), "http://existing.proxy:8080")
+            .expect("failed to create proxy");
+        let config = Config::resolve(PathBuf::from("."), ConfigLayer::default(), Vec::new()).unwrap();
+
+        let proxy_settings = load_proxy_settings(&config, &profile,
+            |name| (name == "HTTPS_PROXY").then(|| "http://proxy.corp:8080".to_string()));
+
+        assert_eq!(Some("http://proxy.corp:8080"), proxy_settings.get_proxy_for_url("https://api.github.com/user"));
+    }
+
+    #[test]
+    fn rate_limit_budget_is_recorded_from_responses() {
+        let (base_url, server) = serve_responses(vec![
+            "HTTP/1.1 200 OK\r\nx-ratelimit-limit: 5000\r\nx-ratelimit-remaining: 4321\r\nx-ratelimit-reset: 4102444800\r\nx-ratelimit-resource: core\r\n\r\n{}".to_string(),
+        ]);
+        let http_agent = HttpProxyAgent::new_with_proxy("");
+
+        http_agent.get(&format!("{}/user", base_url)).call().unwrap();
+
+        let host = base_url.trim_start_matches("http://").split(':').next().unwrap().to_string();
+        assert_eq!(4321, http_agent.get_rate_limits()[&format!("{} core", host)].get_remaining());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn secondary_rate_limit_without_retry_after_pauses_the_host() {
+        let (base_url, server) = serve_responses(vec![
+            "HTTP/1.1 403 Forbidden\r\nx-ratelimit-limit: 5000\r\nx-ratelimit-remaining: 4321\r\nx-ratelimit-reset: 4102444800\r\n\r\n{\"message\":\"You have exceeded a secondary rate limit.\"}".to_string(),
+        ]);
+        let http_agent = HttpProxyAgent::new_with_proxy("");
+
+        let result = http_agent.post(&format!("{}/repos/bfrazho/gitty/commits/abc/comments", base_url)).send_string("{}");
+
+        let Err(Error::Status(403, response)) = result.map_err(|error| *error) else { panic!("expected a 403") };
+        assert!(response.into_string().unwrap().contains("secondary rate limit"));
+        let (wait, _) = http_agent.rate_limit.get_wait(&format!("{}/api/graphql", base_url), Utc::now()).unwrap();
+        assert!(wait > chrono::Duration::seconds(55) && wait <= chrono::Duration::seconds(60));
+        server.join().unwrap();
+    }
+
+    fn quick_retries()-> RetryPolicy {
+        RetryPolicy::new(3, Duration::from_millis(1), Duration::from_millis(1), 0, Duration::from_secs(5))
+    }
+
+    #[test]
+    fn gets_are_retried_after_server_errors() {
+        let (base_url, server) = serve_responses(vec![
+            "HTTP/1.1 502 Bad Gateway\r\n\r\n".to_string(),
+            "HTTP/1.1 200 OK\r\n\r\nok".to_string(),
+        ]);
+        let http_agent = HttpProxyAgent::new_with_proxy("").with_retry_policy(quick_retries());
+
+        let response = http_agent.get(&format!("{}/user", base_url)).call().unwrap();
+
+        assert_eq!("ok", response.into_string().unwrap());
+        assert_eq!(2, server.join().unwrap().len());
+    }
+
+    #[test]
+    fn posts_are_only_retried_when_marked_idempotent() {
+        let (base_url, server) = serve_responses(vec![
+            "HTTP/1.1 503 Service Unavailable\r\n\r\n".to_string(),
+            "HTTP/1.1 503 Service Unavailable\r\n\r\n".to_string(),
+            "HTTP/1.1 200 OK\r\n\r\n{}".to_string(),
+        ]);
+        let http_agent = HttpProxyAgent::new_with_proxy("").with_retry_policy(quick_retries());
+
+        let comment = http_agent.post(&format!("{}/comments", base_url)).send_string("{}");
+        let query = http_agent.post(&format!("{}/graphql", base_url)).idempotent(true).send_string("{}");
+
+        assert!(matches!(comment.map_err(|error| *error), Err(Error::Status(503, _))));
+        assert!(query.is_ok());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let (base_url, server) = serve_responses(vec!["HTTP/1.1 500 Internal Server Error\r\n\r\n".to_string(); 3]);
+        let http_agent = HttpProxyAgent::new_with_proxy("").with_retry_policy(quick_retries());
+
+        let result = http_agent.get(&format!("{}/user", base_url)).call();
+
+        assert!(matches!(result.map_err(|error| *error), Err(Error::Status(500, _))));
+        assert_eq!(3, server.join().unwrap().len());
+    }
+
+    #[test]
+    fn unchanged_responses_come_from_the_cache() {
+        let (base_url, server) = serve_responses(vec![
+            "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\n\r\n[\"cached\"]".to_string(),
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\n\r\n".to_string(),
+        ]);
+        let http_agent = HttpProxyAgent::new_with_proxy("").with_cache(temporary_cache("unchanged_responses_come_from_the_cache"));
+        let url = format!("{}/repos/bfrazho/gitty/collaborators", base_url);
+
+        let first = http_agent.get(&url).conditional().call().unwrap().into_string().unwrap();
+        let second = http_agent.get(&url).conditional().call().unwrap();
+
+        assert_eq!(200, second.status());
+        assert_eq!(first, second.into_string().unwrap());
+        let requests = server.join().unwrap();
+        assert!(!requests[0].contains("If-None-Match"));
+        assert!(requests[1].contains("If-None-Match: \"v1\""));
+    }
+
+    #[test]
+    fn requests_are_only_cached_when_asked() {
+        let (base_url, server) = serve_responses(vec!["HTTP/1.1 200 OK\r\nETag: \"v1\"\r\n\r\n{}".to_string()]);
+        let http_agent = HttpProxyAgent::new_with_proxy("").with_cache(temporary_cache("requests_are_only_cached_when_asked"));
+
+        http_agent.get(&format!("{}/user", base_url)).call().unwrap();
+
+        assert_eq!(None, http_agent.get(&format!("{}/user", base_url)).cached());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn trace_records_route_and_status() {
+        let path = std::env::temp_dir().join("gitty_trace_records_route_and_status.log");
+        fs::remove_file(&path).unwrap_or_default();
+        let (base_url, server) = serve_responses(vec!["HTTP/1.1 404 Not Found\r\n\r\n".to_string()]);
+        let http_agent = HttpProxyAgent::new_with_proxy("").with_trace(HttpTrace::open(&path, false).unwrap());
+
+        let result = http_agent.get(&format!("{}/user", base_url)).set("Authorization", "Bearer ghp_secret").call();
+
+        assert!(matches!(result.map_err(|error| *error), Err(Error::Status(404, _))));
+        let log = fs::read_to_string(&path).unwrap();
+        assert!(log.contains(&format!("GET {}/user directly -> 404 Not Found in", base_url)));
+        assert!(!log.contains("ghp_secret"));
+        server.join().unwrap();
     }
 }
@@ -0,0 +1,153 @@
+use std::{collections::hash_map::DefaultHasher, fs, hash::{Hash, Hasher}, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Deserialize};
+use ureq::{Error, Request, Response};
+
+use crate::{error::GittyError, http_agent::HttpResult, profile::write_private_file};
+
+/// Headers that describe how the original body was transferred rather than the body itself.
+const TRANSFER_HEADERS: [&str; 4] = ["content-length", "content-encoding", "transfer-encoding", "connection"];
+
+/// A GET response kept on disk together with the validators needed to ask GitHub whether it changed.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+pub struct CachedResponse {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    headers: Vec<(String, String)>,
+    body: String,
+    stored_at: DateTime<Utc>,
+}
+
+impl CachedResponse {
+    pub fn from_response(url: &str, response: Response, now: DateTime<Utc>)-> HttpResult<Self> {
+        let etag = response.header("etag").map(str::to_string);
+        let last_modified = response.header("last-modified").map(str::to_string);
+        let headers = get_headers(&response);
+        let body = read_body(response)?;
+        Ok(Self{url: url.to_string(), etag, last_modified, headers, body, stored_at: now})
+    }
+
+    /// Without an ETag or Last-Modified there is nothing to ask GitHub with, so it is not worth keeping.
+    pub fn has_validators(&self)-> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+
+    pub fn get_stored_at(&self)-> DateTime<Utc> {
+        self.stored_at
+    }
+
+    pub fn add_validators(&self, mut request: Request)-> Request {
+        if let Some(etag) = &self.etag {
+            request = request.set("If-None-Match", etag);
+        }
+        if let Some(last_modified) = &self.last_modified {
+            request = request.set("If-Modified-Since", last_modified);
+        }
+        request
+    }
+
+    pub fn to_response(&self)-> HttpResult {
+        build_response(200, "OK", &self.headers, &self.body)
+    }
+}
+
+pub fn get_headers(response: &Response)-> Vec<(String, String)> {
+    response.headers_names().into_iter()
+        .filter(|name| !TRANSFER_HEADERS.contains(&name.to_lowercase().as_str()))
+        .filter_map(|name| response.header(&name).map(|value| (name.clone(), value.to_string())))
+        .collect()
+}
+
+fn build_response(status: u16, status_text: &str, headers: &[(String, String)], body: &str)-> HttpResult {
+    let headers: String = headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+    format!("HTTP/1.1 {} {}\r\n{}\r\n{}", status, status_text, headers, body).parse().map_err(Box::new)
+}
+
+fn read_body(response: Response)-> HttpResult<String> {
+    response.into_string().map_err(|error| Box::new(Error::from(error)))
+}
+
+/// Reads the whole body of `response`, handing back an equivalent response that can still be read.
+pub fn buffer_response(response: Response)-> HttpResult<(Response, String)> {
+    let status = response.status();
+    let status_text = response.status_text().to_string();
+    let headers = get_headers(&response);
+    let body = read_body(response)?;
+    Ok((build_response(status, &status_text, &headers, &body)?, body))
+}
+
+/// One JSON file per URL under the profile's cache directory, so accounts never see each other's responses.
+#[derive(Clone, Debug)]
+pub struct HttpCache {
+    dir: PathBuf,
+}
+
+impl HttpCache {
+    pub fn new(dir: PathBuf)-> Self {
+        Self{dir}
+    }
+
+    fn get_path(&self, url: &str)-> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        url.hash(&mut hasher);
+        self.dir.join(format!("{:016x}.json", hasher.finish()))
+    }
+
+    pub fn get(&self, url: &str)-> Option<CachedResponse> {
+        let contents = fs::read_to_string(self.get_path(url)).ok()?;
+        serde_json::from_str::<CachedResponse>(&contents).ok()
+            .filter(|cached| cached.url == url)
+    }
+
+    pub fn put(&self, cached: &CachedResponse)-> Result<(), GittyError> {
+        write_private_file(&self.get_path(&cached.url), &serde_json::to_string(cached)?)
+    }
+}
+
+#[cfg(test)]
+pub mod testing {
+    use std::{env, fs};
+
+    use super::HttpCache;
+
+    pub fn temporary_cache(test_name: &str)-> HttpCache {
+        let dir = env::temp_dir().join("gitty_http_cache_test").join(test_name);
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        HttpCache::new(dir)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+    use super::testing::temporary_cache;
+
+    fn now()-> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 4, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn responses_without_validators_cannot_be_revalidated() {
+        let response: Response = "HTTP/1.1 200 OK\r\n\r\n[]".parse().unwrap();
+        assert!(!CachedResponse::from_response("https://api.github.com/user", response, now()).unwrap().has_validators());
+    }
+
+    #[test]
+    fn cached_response_keeps_headers_and_body() {
+        let cache = temporary_cache("cached_response_keeps_headers_and_body");
+        let response: Response = "HTTP/1.1 200 OK\r\nETag: \"abc\"\r\nLink: <https://api.github.com/next>; rel=\"next\"\r\nContent-Length: 2\r\n\r\n[]".parse().unwrap();
+        let cached = CachedResponse::from_response("https://api.github.com/repos/bfrazho/gitty/collaborators", response, now()).unwrap();
+
+        cache.put(&cached).unwrap();
+        let restored = cache.get("https://api.github.com/repos/bfrazho/gitty/collaborators").unwrap().to_response().unwrap();
+
+        assert_eq!(Some("<https://api.github.com/next>; rel=\"next\""), restored.header("link"));
+        assert_eq!(None, restored.header("content-length"));
+        assert_eq!("[]", restored.into_string().unwrap());
+        assert_eq!(None, cache.get("https://api.github.com/user"));
+    }
+}
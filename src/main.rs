@@ -1,52 +1,549 @@
-use std::time::Duration;
+use std::{env, path::Path, process::exit, time::{Duration, Instant}};
+use clap::Parser;
+use cli::{parse_commit_range, print_completions, print_man_page, Cli, Command, ProxyCommand, TokenCommand};
 use colored::Colorize;
 use chrono::Local;
 use collaborator::{ask_who_they_are_working_with, Collaborator};
-use http_agent::HttpProxyAgent;
-use repository::{get_repository_url, GitRepository};
-use token_retriever::get_github_token_and_prompt_if_not_found;
+use config::{get_cache_dir, get_user_config_path, load_config, Config};
+use credential_store::{get_passphrase, migrate_credentials, open_credential_store, CredentialStore, CredentialStoreKind, EncryptedCredentialStore, PlaintextCredentialStore, GITHUB_TOKEN_KEY};
+use error::GittyError;
+use http_agent::{load_proxy_settings, prompt_for_proxy, HttpProxyAgent};
+use http_cache::HttpCache;
+use http_trace::HttpTrace;
+use proxy::get_proxy_credentials_key;
+use profile::{select_profile, Profile};
+use device_flow::{get_device_flow_base_url, DeviceFlow, DEFAULT_OAUTH_SCOPES};
+use github_app::GitHubAppAuth;
+use remote::RemoteLocation;
+use fork::choose_upstream;
+use repository::{get_remote_head_branch, get_remote_location, list_remotes, Auth, GitRepository};
+use state::{get_repository_state_key_prefix, load_state};
+use token_retriever::{prompt_for_github_token_and_save, resolve_github_token};
+use token_validator::validate_token;
 use user_input_generator::TextInputGeneratorTrait;
+use watcher::{approve_new_commits, reconcile_missed_commits, retry_transient_errors, Backoff};
+use workspace::resolve_workspace;
 
-use crate::{repository::{RepositoryTrait, get_main_branch_name}, user_input_generator::{InquireTextInputGenerator, InquireMultiSelectGenerator}};
+use crate::{repository::get_main_branch_name, user_input_generator::{InquireTextInputGenerator, InquireMultiSelectGenerator}};
+mod branch;
+mod cli;
 mod collaborator;
+mod config;
+mod device_flow;
+mod credential_store;
+mod remote;
 mod repository;
+mod retry;
 mod token_retriever;
+mod token_validator;
 mod user_input_generator;
 mod commit;
+mod error;
+mod fork;
+mod github_app;
 mod http_agent;
+mod http_cache;
+mod http_trace;
+mod profile;
+mod proxy;
+mod rate_limit;
+mod state;
+mod tls;
+mod watcher;
+mod workspace;
 
 
-fn create_git_repository(user_input_generator: &mut dyn TextInputGeneratorTrait)-> GitRepository {
-    let url = get_repository_url();
-    let main_branch = get_main_branch_name();
-    println!("Org: {}, Repo: {}, Main branch name: {}", url.get_org_name(), url.get_repository_name(), main_branch);
-    let github_token = get_github_token_and_prompt_if_not_found(user_input_generator);
-    {
-        let token = github_token;let url = url;
-        GitRepository::new(token, url, main_branch)
+/// The branch to watch: the one asked for, else what the remote's HEAD points at, else GitHub's default branch,
+/// else the first of `default_branches` that exists in `checkout`.
+fn resolve_main_branch(config: &Config, checkout: Option<&Path>, remote_head: Option<String>, repository: &GitRepository, http_agent: &HttpProxyAgent)-> Result<String, GittyError> {
+    if let Some(branch) = config.get_branch().map(str::to_string).or(remote_head) {
+        return Ok(branch)
+    }
+    match repository.get_default_branch(http_agent) {
+        Ok(branch) => Ok(branch),
+        Err(error) => {
+            eprintln!("Failed to get the default branch of {}/{}: {}", repository.get_org_name(), repository.get_repository_name(), error);
+            match checkout {
+                Some(checkout) => get_main_branch_name(checkout, config.get_default_branches()),
+                None => Err(error),
+            }
+        },
     }
 }
 
-fn main() {
+/// The branch HEAD of the local remote `remote_name`, or of the default remote, points at.
+fn read_remote_head(checkout: &Path, remote_name: Option<&str>)-> Option<String> {
+    get_remote_head_branch(checkout, remote_name)
+        .unwrap_or_else(|error| {
+            eprintln!("Failed to read the remote HEAD: {}", error);
+            None
+        })
+}
+
+/// Settles which branch `repository` is watched on and says so.
+fn with_main_branch(config: &Config, checkout: Option<&Path>, remote_head: Option<String>, repository: GitRepository, http_agent: &HttpProxyAgent)-> Result<GitRepository, GittyError> {
+    let main_branch = resolve_main_branch(config, checkout, remote_head, &repository, http_agent)?;
+    println!("Org: {}, Repo: {}, Main branch name: {}", repository.get_org_name(), repository.get_repository_name(), main_branch);
+    Ok(repository.with_main_branch_name(main_branch))
+}
+
+/// The repository is created before its branch is known, since finding that may take an API call.
+fn create_git_repository(config: &Config, auth: Auth, remote: RemoteLocation)-> GitRepository {
+    GitRepository::new_with_auth(auth, remote, String::new())
+        .with_max_history_pages(config.get_max_history_pages())
+        .with_approval_message(config.get_approval_message())
+}
+
+fn connect_with_token(config: &Config, remote: RemoteLocation, credential_store: &dyn CredentialStore, http_agent: &HttpProxyAgent, user_input_generator: &mut dyn TextInputGeneratorTrait)-> Result<GitRepository, GittyError> {
+    let repository_path = format!("{}/{}.git", remote.get_owner_path(), remote.get_repo());
+    let (github_token, token_source) = resolve_github_token(remote.get_host(), &repository_path, credential_store, user_input_generator, |name| env::var(name).ok())?;
+    println!("Using github token from {}", token_source);
+    let mut repository = create_git_repository(config, Auth::Token(github_token), remote);
+    validate_token(&mut repository, &token_source, http_agent, credential_store, user_input_generator, config.get_token_expiry_warning_days())?;
+    Ok(repository)
+}
+
+/// Fetches the first installation token up front so a misconfigured app fails here instead of on the first poll.
+fn connect_as_github_app(config: &Config, remote: RemoteLocation, app_id: u64, private_key_path: &Path, http_agent: &HttpProxyAgent)-> Result<GitRepository, GittyError> {
+    let app = GitHubAppAuth::from_private_key_file(app_id, private_key_path, http_agent.clone())?;
+    let repository = create_git_repository(config, Auth::GitHubApp(Box::new(app)), remote);
+    repository.get_token()?;
+    println!("Authenticated to {} as GitHub App {}", repository.get_host(), app_id);
+    Ok(repository)
+}
+
+/// Authenticates to `remote` the way the config says to, with a GitHub App or a token.
+fn authenticate(config: &Config, remote: RemoteLocation, credential_store: &dyn CredentialStore, http_agent: &HttpProxyAgent, user_input_generator: &mut dyn TextInputGeneratorTrait)-> Result<GitRepository, GittyError> {
+    match config.get_github_app() {
+        Some((app_id, private_key_path)) => connect_as_github_app(config, remote, app_id, private_key_path, http_agent),
+        None => connect_with_token(config, remote, credential_store, http_agent, user_input_generator),
+    }
+}
+
+/// The credential profile for the repository's host, unless one was chosen explicitly.
+fn resolve_profile(config: &Config)-> Result<Profile, GittyError> {
+    let host = match config.get_profile() {
+        Some(_) => String::new(),
+        None => get_remote_location(config.get_repo_path(), config.get_remote())?.get_host().to_string(),
+    };
+    select_profile(config, &host)
+}
+
+fn create_http_agent(config: &Config, profile: &Profile, credential_store: &dyn CredentialStore)-> Result<HttpProxyAgent, GittyError> {
+    let proxy_settings = load_proxy_settings(config, profile, |name| env::var(name).ok());
+    HttpProxyAgent::new(proxy_settings, config.get_tls_settings().clone(), credential_store)
+        .map(|http_agent| http_agent.with_retry_policy(config.get_retry_policy().clone()))
+        .map(|http_agent| match get_cache_dir() {
+            Some(cache_dir) => http_agent.with_cache(HttpCache::new(cache_dir.join("http").join(profile.get_name()))),
+            None => http_agent,
+        })
+        .and_then(|http_agent| match config.get_trace_http() {
+            Some(trace_path) => Ok(http_agent.with_trace(HttpTrace::open(trace_path, config.get_trace_http_bodies())?)),
+            None => Ok(http_agent),
+        })
+}
+
+/// Without an explicit remote, a fork is swapped for its upstream, or watched alongside it, upstream first.
+fn resolve_watched_repositories(config: &Config, repository: GitRepository, http_agent: &HttpProxyAgent)-> Result<Vec<GitRepository>, GittyError> {
+    let repo_path = config.get_repo_path();
+    if config.get_remote().is_some() {
+        return Ok(vec![with_main_branch(config, Some(repo_path), read_remote_head(repo_path, config.get_remote()), repository, http_agent)?])
+    }
+    let parent = match repository.get_fork_parent(http_agent) {
+        Ok(Some(parent)) => parent,
+        Ok(None) => return Ok(vec![with_main_branch(config, Some(repo_path), read_remote_head(repo_path, None), repository, http_agent)?]),
+        Err(error) => {
+            eprintln!("Failed to check whether {}/{} is a fork: {}", repository.get_org_name(), repository.get_repository_name(), error);
+            return Ok(vec![with_main_branch(config, Some(repo_path), read_remote_head(repo_path, None), repository, http_agent)?])
+        },
+    };
+    let remotes: Vec<(String, RemoteLocation)> = list_remotes(repo_path)?.iter()
+        .filter_map(|(name, url)| RemoteLocation::try_from(url).ok().map(|remote| (name.clone(), remote)))
+        .collect();
+    let (remote_name, upstream) = choose_upstream(&remotes, parent);
+    println!("{}/{} is a fork of {}, watching {}{}",
+        repository.get_org_name(), repository.get_repository_name(), upstream,
+        remote_name.as_deref().map_or("it".to_string(), |name| format!("remote {}", name)),
+        if config.get_watch_fork_and_upstream() { " and the fork" } else { " instead, pass --remote to watch the fork" });
+    let upstream = repository.with_remote(upstream);
+    let upstream_head = remote_name.and_then(|remote_name| read_remote_head(repo_path, Some(&remote_name)));
+    let upstream = with_main_branch(config, Some(repo_path), upstream_head, upstream, http_agent)?;
+    Ok(if config.get_watch_fork_and_upstream() {
+        vec![upstream, with_main_branch(config, Some(repo_path), read_remote_head(repo_path, None), repository, http_agent)?]
+    } else {
+        vec![upstream]
+    })
+}
+
+fn connect_watched(config: &Config, user_input_generator: &mut dyn TextInputGeneratorTrait)-> Result<(Vec<GitRepository>, HttpProxyAgent), GittyError> {
+    let profile = resolve_profile(config)?;
+    let credential_store = open_credential_store(config.get_credential_store(), &profile, user_input_generator)?;
+    let http_agent = create_http_agent(config, &profile, credential_store.as_ref())?;
+    let remote = get_remote_location(config.get_repo_path(), config.get_remote())?;
+    let repository = authenticate(config, remote, credential_store.as_ref(), &http_agent, user_input_generator)?;
+    let repositories = resolve_watched_repositories(config, repository, &http_agent)?;
+    Ok((repositories, http_agent))
+}
+
+/// The repository commands act on: the upstream when the checkout is a fork.
+fn connect(config: &Config, user_input_generator: &mut dyn TextInputGeneratorTrait)-> Result<(GitRepository, HttpProxyAgent), GittyError> {
+    let (repositories, http_agent) = connect_watched(config, user_input_generator)?;
+    let repository = repositories.into_iter().next()
+        .ok_or_else(|| GittyError::Config("no repository to watch".to_string()))?;
+    Ok((repository, http_agent))
+}
+
+/// Connects once, to the first member, and gives every other member the same credentials.
+fn connect_workspace(config: &Config, repositories: &[String], dir: Option<&Path>, user_input_generator: &mut dyn TextInputGeneratorTrait)-> Result<(Vec<GitRepository>, HttpProxyAgent), GittyError> {
+    let default_host = config.get_profile()
+        .and_then(|profile| config.get_profiles().get(profile))
+        .map_or("github.com", |settings| settings.get_host());
+    let repositories = if repositories.is_empty() { config.get_workspace_repositories() } else { repositories };
+    let members = resolve_workspace(repositories, dir.or(config.get_workspace_dir()), config.get_remote(), default_host)?;
+    let profile = select_profile(config, members[0].get_remote().get_host())?;
+    let credential_store = open_credential_store(config.get_credential_store(), &profile, user_input_generator)?;
+    let http_agent = create_http_agent(config, &profile, credential_store.as_ref())?;
+    let first = authenticate(config, members[0].get_remote().clone(), credential_store.as_ref(), &http_agent, user_input_generator)?;
+
+    let mut watched = Vec::new();
+    for member in members.iter() {
+        let repository = first.with_remote(member.get_remote().clone());
+        let remote_head = member.get_checkout().and_then(|checkout| read_remote_head(checkout, config.get_remote()));
+        match with_main_branch(config, member.get_checkout(), remote_head, repository, &http_agent) {
+            Ok(repository) => watched.push(repository),
+            Err(error) => eprintln!("Not watching {}: {}", member.get_remote(), error),
+        }
+    }
+    if watched.is_empty() {
+        return Err(GittyError::Config("none of the workspace repositories could be watched".to_string()))
+    }
+    println!("Watching {} repositories", watched.len());
+    Ok((watched, http_agent))
+}
+
+fn watch(config: &Config)-> Result<(), GittyError> {
     let mut user_input_generator = InquireTextInputGenerator::new();
-    let mut collaborator_input_generator = InquireMultiSelectGenerator::new();
+    let (repositories, http_agent) = connect_watched(config, &mut user_input_generator)?;
+    watch_repositories(config, &repositories, &http_agent)
+}
 
-    let repository = create_git_repository(&mut user_input_generator);
-    let http_agent = HttpProxyAgent::new(&mut user_input_generator);
-    let collaborators: Vec<Collaborator> = repository.get_collaborators(&http_agent);
-    let selected_collaborators = ask_who_they_are_working_with(&mut collaborator_input_generator, collaborators);
-    let mut timestamp = Local::now();
+fn watch_workspace(config: &Config, repositories: &[String], dir: Option<&Path>)-> Result<(), GittyError> {
+    let mut user_input_generator = InquireTextInputGenerator::new();
+    let (repositories, http_agent) = connect_workspace(config, repositories, dir, &mut user_input_generator)?;
+    watch_repositories(config, &repositories, &http_agent)
+}
+
+/// Asks once who is pairing, from everyone who can be picked on any of `repositories`, then polls each of them
+/// on its own schedule so a repository that keeps failing backs off without slowing down the others.
+fn watch_repositories(config: &Config, repositories: &[GitRepository], http_agent: &HttpProxyAgent)-> Result<(), GittyError> {
+    let mut collaborator_input_generator = InquireMultiSelectGenerator::new();
+    let mut collaborators: Vec<Collaborator> = Vec::new();
+    for repository in repositories.iter() {
+        collaborators.extend(match repository.get_cached_collaborators(http_agent, config.get_collaborator_permission()) {
+            Some((collaborators, stored_at)) => {
+                println!("Showing collaborators as of {}", stored_at.with_timezone(&Local).to_rfc3339());
+                collaborators
+            },
+            None => retry_transient_errors(
+                &mut Backoff::new(Duration::new(5, 0), Duration::new(300, 0)),
+                || repository.get_collaborators(http_agent, config.get_collaborator_permission())
+            )?,
+        });
+    }
+    collaborators.sort();
+    collaborators.dedup();
+    let selected_collaborators = ask_who_they_are_working_with(&mut collaborator_input_generator, collaborators)?;
+    for repository in repositories.iter() {
+        if let Err(error) = repository.get_collaborators(http_agent, config.get_collaborator_permission()) {
+            eprintln!("Failed to refresh cached collaborators: {}", error);
+        }
+    }
+    let mut state = load_state()?;
+    state.start_session(Local::now());
 
     print_nyan_cat();
 
+    let interval = Duration::new(config.get_poll_interval_seconds(), 0);
+    let mut backoffs: Vec<Backoff> = repositories.iter().map(|_| Backoff::new(interval, Duration::new(3600, 0).max(interval))).collect();
+    for (repository, backoff) in repositories.iter().zip(backoffs.iter_mut()) {
+        let result = repository.expand_branch_patterns(http_agent, config.get_watch_branches())
+            .and_then(|branches| reconcile_missed_commits(repository, http_agent, &branches, &selected_collaborators, &mut state));
+        if let Err(error) = result {
+            eprintln!("Failed to reconcile missed commits on {}/{}: {}", repository.get_org_name(), repository.get_repository_name(), error);
+            backoff.record_failure();
+        }
+    }
+    let started_at = Instant::now();
+    let mut due_at: Vec<Instant> = backoffs.iter().map(|backoff| started_at + backoff.next_delay()).collect();
     loop{
-        let commits = repository.get_commits_matching_collaborators_since_timestamp(&http_agent, &selected_collaborators, timestamp);
-        timestamp = Local::now();
-        commits.iter().for_each(|commit| {
-            println!("commit: {:?}", commit);
-            repository.post_comment_on_commit_that_you_approve_it(&http_agent, commit);
-        });
-        std::thread::sleep(Duration::new(300, 0))
+        let next_due_at = due_at.iter().min().copied().unwrap_or_else(Instant::now);
+        std::thread::sleep(next_due_at.saturating_duration_since(Instant::now()));
+        for (index, repository) in repositories.iter().enumerate() {
+            if due_at[index] > Instant::now() {
+                continue;
+            }
+            let backoff = &mut backoffs[index];
+            let result = repository.expand_branch_patterns(http_agent, config.get_watch_branches())
+                .and_then(|branches| approve_new_commits(repository, http_agent, &branches, &selected_collaborators, &mut state));
+            match result {
+                Ok(()) => backoff.record_success(),
+                Err(error) => {
+                    backoff.record_failure();
+                    eprintln!("Failed to check {}/{} for new commits: {}, trying again in {}s",
+                        repository.get_org_name(), repository.get_repository_name(), error, backoff.next_delay().as_secs());
+                },
+            }
+            due_at[index] = Instant::now() + backoff.next_delay();
+        }
+        state.set_rate_limits(http_agent.get_rate_limits());
+        if let Err(error) = state.save() {
+            eprintln!("Failed to save rate limits: {}", error);
+        }
+    }
+}
+
+fn approve(config: &Config, target: &str)-> Result<(), GittyError> {
+    let mut user_input_generator = InquireTextInputGenerator::new();
+    let (repository, http_agent) = connect(config, &mut user_input_generator)?;
+    let oids = match parse_commit_range(target) {
+        Some((base, head)) => repository.get_commit_oids_in_range(&http_agent, base, head)?,
+        None => vec![target.to_string()],
+    };
+
+    let mut state = load_state()?;
+    let state_key = repository.get_state_key();
+    for oid in oids.iter() {
+        if state.get_branch_state_mut(&state_key).is_approved(oid) {
+            println!("{} is already approved", oid);
+            continue;
+        }
+        repository.post_approval_comment(&http_agent, oid)?;
+        state.get_branch_state_mut(&state_key).mark_approved(oid);
+        state.save()?;
+        println!("Approved {}", oid);
+    }
+    Ok(())
+}
+
+/// Shows the branches of the chosen remote, or of every remote of the checkout so a fork's upstream is included.
+fn status(config: &Config)-> Result<(), GittyError> {
+    let remotes = match config.get_remote() {
+        Some(_) => vec![get_remote_location(config.get_repo_path(), config.get_remote())?],
+        None => list_remotes(config.get_repo_path())?.iter()
+            .filter_map(|(_, url)| RemoteLocation::try_from(url).ok())
+            .collect(),
+    };
+    let prefixes: Vec<String> = remotes.iter().map(get_repository_state_key_prefix).collect();
+    let state = load_state()?;
+    match state.get_session_started_at() {
+        Some(session_started_at) => println!("Last session started: {}", session_started_at.to_rfc3339()),
+        None => println!("gitty has not watched any repository yet"),
+    }
+    let branch_states: Vec<_> = state.get_branch_states().iter()
+        .filter(|(key, _)| prefixes.iter().any(|prefix| key.starts_with(prefix)))
+        .collect();
+    if branch_states.is_empty() {
+        println!("gitty has not watched this repository yet");
+    }
+    branch_states.into_iter().for_each(|(key, branch_state)| {
+        println!("{}", key.bold());
+        println!("  last seen commit: {}", branch_state.get_last_seen_oid().unwrap_or("none"));
+        println!("  last polled:      {}", branch_state.get_last_polled_at().map(|polled_at| polled_at.to_rfc3339()).unwrap_or("never".to_string()));
+        println!("  approved commits: {}", branch_state.get_approved_oids().len());
+    });
+    state.get_rate_limits().iter().for_each(|(key, budget)| {
+        println!("{} {}", "rate limit".bold(), key);
+        println!("  remaining:        {}/{}", budget.get_remaining(), budget.get_limit());
+        println!("  resets:           {}", budget.get_reset_at().with_timezone(&Local).to_rfc3339());
+        println!("  as of:            {}", budget.get_observed_at().with_timezone(&Local).to_rfc3339());
+    });
+    Ok(())
+}
+
+fn list_collaborators(config: &Config, permission: Option<&str>)-> Result<(), GittyError> {
+    let mut user_input_generator = InquireTextInputGenerator::new();
+    let (repository, http_agent) = connect(config, &mut user_input_generator)?;
+    let permission = match permission {
+        Some(permission) => permission.parse()?,
+        None => config.get_collaborator_permission(),
+    };
+    repository.get_collaborators(&http_agent, permission)?
+        .iter()
+        .for_each(|collaborator| println!("{}", collaborator));
+    Ok(())
+}
+
+fn list_remote_locations(config: &Config)-> Result<(), GittyError> {
+    for (name, url) in list_remotes(config.get_repo_path())? {
+        match RemoteLocation::try_from(&url) {
+            Ok(remote) => println!("{} {} ({})", name.bold(), url.to_bstring(), remote),
+            Err(error) => println!("{} {} (not a GitHub repository: {})", name.bold(), url.to_bstring(), error),
+        }
+    }
+    Ok(())
+}
+
+fn print_config(config: &Config)-> Result<(), GittyError> {
+    println!("repo:                    {}", config.get_repo_path().display());
+    println!("remote:                  {}", config.get_remote().unwrap_or("default fetch remote, or the upstream of a fork"));
+    println!("watch fork and upstream: {}", config.get_watch_fork_and_upstream());
+    if !config.get_workspace_repositories().is_empty() {
+        println!("workspace repositories:  {}", config.get_workspace_repositories().join(", "));
+    }
+    if let Some(workspace_dir) = config.get_workspace_dir() {
+        println!("workspace directory:     {}", workspace_dir.display());
+    }
+    println!("branch:                  {}", config.get_branch().unwrap_or("remote HEAD, else the default branch on GitHub"));
+    println!("default branches:        {} (when GitHub cannot be asked)", config.get_default_branches().join(", "));
+    if !config.get_watch_branches().is_empty() {
+        println!("also watching:           {}", config.get_watch_branches().join(", "));
+    }
+    println!("poll interval:           {}s", config.get_poll_interval_seconds());
+    println!("approval message:        {}", config.get_approval_message());
+    println!("collaborator permission: {}", config.get_collaborator_permission());
+    println!("max history pages:       {}", config.get_max_history_pages());
+    println!("token expiry warning:    {} days", config.get_token_expiry_warning_days());
+    println!("proxy:                   {}", config.get_proxy().unwrap_or("from HTTPS_PROXY, HTTP_PROXY or ALL_PROXY"));
+    config.get_proxy_rules().iter().for_each(|(host, proxy)| println!("  {}: {}", host, proxy));
+    config.get_tls_settings().keys().for_each(|host| println!("tls settings for:        {}", host));
+    let retry_policy = config.get_retry_policy();
+    println!("request retries:         {} attempts, {}ms to {}ms backoff, {}% jitter", retry_policy.get_max_attempts(),
+        retry_policy.get_initial_backoff().as_millis(), retry_policy.get_max_backoff().as_millis(), retry_policy.get_jitter_percent());
+    println!("request timeout:         {}s", retry_policy.get_timeout().as_secs());
+    if let Some(trace_path) = config.get_trace_http() {
+        println!("http trace:              {}{}", trace_path.display(), if config.get_trace_http_bodies() { " with bodies" } else { "" });
+    }
+    if let Some((app_id, private_key_path)) = config.get_github_app() {
+        println!("github app:              {} with key {}", app_id, private_key_path.display());
+    }
+    println!("config files:");
+    config.get_sources().iter().for_each(|source| println!("  {}", source.display()));
+    if let Some(user_config_path) = get_user_config_path().filter(|path| !path.is_file()) {
+        println!("  (no user config at {})", user_config_path.display());
+    }
+    Ok(())
+}
+
+/// Runs the device flow against the repository's host and keeps the token in the credential store.
+fn login(config: &Config)-> Result<(), GittyError> {
+    let client_id = config.get_oauth_client_id()
+        .ok_or_else(|| GittyError::Config("set oauth_client_id to the client id of an OAuth app with device flow enabled".to_string()))?;
+    let profile = resolve_profile(config)?;
+    let mut user_input_generator = InquireTextInputGenerator::new();
+    let remote = get_remote_location(config.get_repo_path(), config.get_remote())?;
+    let host = remote.get_host();
+    let credential_store = open_credential_store(config.get_credential_store(), &profile, &mut user_input_generator)?;
+    let http_agent = create_http_agent(config, &profile, credential_store.as_ref())?;
+    let device_flow = DeviceFlow::new(&get_device_flow_base_url(&remote), client_id, DEFAULT_OAUTH_SCOPES);
+
+    let device_code = device_flow.request_device_code(&http_agent)?;
+    println!("Open {} and enter the code {}", device_code.get_verification_uri(), device_code.get_user_code().bold());
+    let github_token = device_flow.poll_for_token(&http_agent, &device_code)?;
+    credential_store.set(GITHUB_TOKEN_KEY, &github_token)?;
+    println!("Logged in to {}, saved token for profile {} to {}", host, profile.get_name(), credential_store.describe());
+    Ok(())
+}
+
+fn proxy(config: &Config, action: &ProxyCommand)-> Result<(), GittyError> {
+    let profile = resolve_profile(config)?;
+    let mut user_input_generator = InquireTextInputGenerator::new();
+    match action {
+        ProxyCommand::Set => {
+            let proxy = prompt_for_proxy(&profile, &mut user_input_generator)?;
+            match proxy.trim() {
+                "" => println!("Saved to {}, requests will go directly", profile.get_proxy_path().display()),
+                proxy => println!("Saved to {}, requests that cannot reach GitHub directly will go through {}", profile.get_proxy_path().display(), proxy),
+            }
+        },
+        ProxyCommand::Login { proxy } => {
+            let username = user_input_generator.get_text_input(&format!("Username for {}", proxy))
+                .ok_or_else(|| GittyError::Config("no proxy username was entered".to_string()))?;
+            let password = user_input_generator.get_password_input(&format!("Password for {}", proxy))
+                .ok_or_else(|| GittyError::Config("no proxy password was entered".to_string()))?;
+            let credential_store = open_credential_store(config.get_credential_store(), &profile, &mut user_input_generator)?;
+            credential_store.set(&get_proxy_credentials_key(proxy), &format!("{}:{}", username, password))?;
+            println!("Saved credentials for {} to {}", proxy, credential_store.describe());
+        },
+        ProxyCommand::Show => {
+            let remote = get_remote_location(config.get_repo_path(), config.get_remote())?;
+            let host = remote.get_host();
+            let proxy_settings = load_proxy_settings(config, &profile, |name| env::var(name).ok());
+            match proxy_settings.get_proxy_for_host("https", host) {
+                Some(proxy) => println!("{} falls back to {} when it cannot be reached directly", host, proxy),
+                None => println!("{} is always reached directly", host),
+            }
+        },
+    }
+    Ok(())
+}
+
+fn token(config: &Config, action: &TokenCommand)-> Result<(), GittyError> {
+    let profile = resolve_profile(config)?;
+    let mut user_input_generator = InquireTextInputGenerator::new();
+    match action {
+        TokenCommand::Set => {
+            let credential_store = open_credential_store(config.get_credential_store(), &profile, &mut user_input_generator)?;
+            prompt_for_github_token_and_save(credential_store.as_ref(), &mut user_input_generator)?;
+            println!("Saved token for profile {} to {}", profile.get_name(), credential_store.describe());
+        },
+        TokenCommand::Show => {
+            let credential_store = open_credential_store(config.get_credential_store(), &profile, &mut user_input_generator)?;
+            match credential_store.get(GITHUB_TOKEN_KEY)? {
+                Some(_) => println!("Token for profile {} is stored in {}", profile.get_name(), credential_store.describe()),
+                None => println!("No token stored for profile {} yet, it will be saved to {}", profile.get_name(), credential_store.describe()),
+            }
+        },
+        TokenCommand::Migrate => {
+            let plaintext = PlaintextCredentialStore::new(profile.get_dir().to_path_buf());
+            let encrypted_path = profile.get_encrypted_credentials_path();
+            let passphrase = get_passphrase(!encrypted_path.exists(), &mut user_input_generator)?;
+            let encrypted = EncryptedCredentialStore::new(encrypted_path, passphrase);
+            match migrate_credentials(&plaintext, &encrypted, &plaintext.keys()?)?.as_slice() {
+                [] => println!("Nothing to migrate for profile {}", profile.get_name()),
+                migrated => println!("Moved {} into {}", migrated.join(", "), encrypted.describe()),
+            }
+            if config.get_credential_store() == Some(CredentialStoreKind::Plaintext) {
+                println!("Remove credential_store = \"plaintext\" from your config to use the encrypted store");
+            }
+        },
+    }
+    Ok(())
+}
+
+fn audit()-> Result<(), GittyError> {
+    load_state()?.get_branch_states().iter().for_each(|(key, branch_state)| {
+        branch_state.get_approved_oids().iter().for_each(|oid| println!("{} {}", key, oid));
+    });
+    Ok(())
+}
+
+fn run(cli: &Cli)-> Result<(), GittyError> {
+    let config = load_config(cli)?;
+    match cli.get_command() {
+        Command::Watch => watch(&config),
+        Command::Approve { target } => approve(&config, target),
+        Command::Status => status(&config),
+        Command::Workspace { repositories, dir } => watch_workspace(&config, repositories, dir.as_deref()),
+        Command::Remotes => list_remote_locations(&config),
+        Command::Collaborators { permission } => list_collaborators(&config, permission.as_deref()),
+        Command::Config => print_config(&config),
+        Command::Login => login(&config),
+        Command::Proxy { action } => proxy(&config, action),
+        Command::Token { action } => token(&config, action),
+        Command::Audit => audit(),
+        Command::Completions { shell } => {
+            print_completions(*shell);
+            Ok(())
+        },
+        Command::Man => print_man_page(),
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if let Err(error) = run(&cli) {
+        eprintln!("{}", error);
+        exit(1);
     }
 }
 
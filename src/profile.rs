@@ -0,0 +1,188 @@
+use std::{collections::BTreeMap, env::current_exe, fs, io::Write, path::{Path, PathBuf}};
+
+use serde::{Serialize, Deserialize};
+
+use crate::{config::{get_config_dir, Config}, error::GittyError};
+
+/// A named profile from the user config, e.g. `[profiles.work] host = "github.example.com"`.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileSettings {
+    host: String,
+}
+
+impl ProfileSettings {
+    #[allow(dead_code)]
+    pub fn new(host: &str)-> Self {
+        Self{host: host.to_string()}
+    }
+
+    pub fn get_host(&self)-> &str {
+        &self.host
+    }
+}
+
+/// Where the credentials for one account live: `$XDG_CONFIG_HOME/gitty/profiles/<name>`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Profile {
+    name: String,
+    dir: PathBuf,
+}
+
+impl Profile {
+    pub fn new(name: &str, dir: PathBuf)-> Self {
+        Self{name: name.to_string(), dir}
+    }
+
+    pub fn get_name(&self)-> &str {
+        &self.name
+    }
+
+    pub fn get_dir(&self)-> &Path {
+        &self.dir
+    }
+
+    pub fn get_encrypted_credentials_path(&self)-> PathBuf {
+        self.dir.join("credentials.enc")
+    }
+
+    pub fn get_proxy_path(&self)-> PathBuf {
+        self.dir.join("proxy")
+    }
+}
+
+/// An explicit `profile` setting wins, then the profile configured for `host`,
+/// and otherwise the host name itself so every host gets its own credentials.
+pub fn select_profile_name(explicit_profile: Option<&str>, profiles: &BTreeMap<String, ProfileSettings>, host: &str)-> String {
+    if let Some(explicit_profile) = explicit_profile {
+        return explicit_profile.to_string();
+    }
+    profiles.iter()
+        .find(|(_, settings)| settings.host.eq_ignore_ascii_case(host))
+        .map(|(name, _)| name.clone())
+        .unwrap_or_else(|| host.to_string())
+}
+
+pub fn select_profile(config: &Config, host: &str)-> Result<Profile, GittyError> {
+    let name = select_profile_name(config.get_profile(), config.get_profiles(), host);
+    let profiles_dir = get_config_dir()
+        .ok_or_else(|| GittyError::Config("could not find a config directory, set XDG_CONFIG_HOME".to_string()))?
+        .join("profiles");
+    Ok(Profile::new(&name, profiles_dir.join(&name)))
+}
+
+/// Writes a secret readable only by the current user, creating its directory if needed.
+pub fn write_private_file(path: &Path, contents: &str)-> Result<(), GittyError> {
+    let to_config_error = |error: std::io::Error| GittyError::Config(format!("failed to write {}: {}", path.display(), error));
+    if let Some(parent) = path.parent() {
+        create_private_dir(parent).map_err(to_config_error)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path).map_err(to_config_error)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600)).map_err(to_config_error)?;
+    }
+    file.write_all(contents.as_bytes()).map_err(to_config_error)
+}
+
+/// Like `write_private_file`, but readers see either the old contents or the new, never a half-written file.
+pub fn replace_private_file(path: &Path, contents: &str)-> Result<(), GittyError> {
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(".tmp");
+    let temporary_path = PathBuf::from(temporary_path);
+    write_private_file(&temporary_path, contents)?;
+    fs::rename(&temporary_path, path)
+        .map_err(|error| GittyError::Config(format!("failed to replace {}: {}", path.display(), error)))
+}
+
+fn create_private_dir(dir: &Path)-> std::io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir)
+}
+
+/// Reads `path`, moving over a file left next to the gitty binary by older versions if `path` does not exist yet.
+pub fn read_with_legacy_fallback(path: &Path, legacy_file_name: &str)-> Option<String> {
+    if let Ok(contents) = fs::read_to_string(path) {
+        return Some(contents);
+    }
+    let legacy_path = current_exe().ok()?.parent()?.join(legacy_file_name);
+    let contents = fs::read_to_string(&legacy_path).ok()?;
+    if write_private_file(path, &contents).is_ok() {
+        println!("Moved {} to {}", legacy_path.display(), path.display());
+        fs::remove_file(&legacy_path).unwrap_or_default();
+    }
+    Some(contents)
+}
+
+#[cfg(test)]
+pub mod testing {
+    use std::{env, fs};
+
+    use super::Profile;
+
+    /// A profile in a throwaway directory under the system temp dir.
+    pub fn temporary_profile(test_name: &str)-> Profile {
+        let dir = env::temp_dir().join("gitty_profile_tests").join(test_name);
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        Profile::new(test_name, dir)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+    use super::testing::temporary_profile;
+
+    #[test]
+    fn profile_defaults_to_host_name() {
+        assert_eq!("github.com", select_profile_name(None, &BTreeMap::new(), "github.com"));
+    }
+
+    #[test]
+    fn profile_is_selected_by_host() {
+        let profiles = BTreeMap::from([
+            ("work".to_string(), ProfileSettings::new("github.some-business.com")),
+            ("personal".to_string(), ProfileSettings::new("github.com")),
+        ]);
+        assert_eq!("work", select_profile_name(None, &profiles, "github.some-business.com"));
+        assert_eq!("personal", select_profile_name(None, &profiles, "github.com"));
+        assert_eq!("other.host", select_profile_name(None, &profiles, "other.host"));
+    }
+
+    #[test]
+    fn explicit_profile_wins() {
+        let profiles = BTreeMap::from([("personal".to_string(), ProfileSettings::new("github.com"))]);
+        assert_eq!("bot", select_profile_name(Some("bot"), &profiles, "github.com"));
+    }
+
+    #[test]
+    fn private_files_are_only_readable_by_owner() {
+        let profile = temporary_profile("private_files_are_only_readable_by_owner");
+        let path = profile.get_dir().join("secret");
+        write_private_file(&path, "secret").unwrap();
+
+        assert_eq!("secret", fs::read_to_string(&path).unwrap());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+    }
+}
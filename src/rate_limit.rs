@@ -0,0 +1,208 @@
+use std::{collections::BTreeMap, sync::{Arc, Mutex}, thread};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Serialize, Deserialize};
+use ureq::Response;
+
+use crate::proxy::parse_url_scheme_and_host;
+
+/// GitHub asks clients that hit a secondary rate limit without a `Retry-After` to wait at least a minute.
+const SECONDARY_RATE_LIMIT_WAIT_SECONDS: i64 = 60;
+
+/// GitHub also answers a missing permission with a 403, so one without `Retry-After` or a used up budget
+/// only counts as a secondary rate limit when its message says so.
+fn is_rate_limit_message(body: &str)-> bool {
+    body.to_lowercase().contains("rate limit")
+}
+
+/// One rate limit bucket as last reported by GitHub's `X-RateLimit-*` headers.
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Clone)]
+pub struct RateLimitBudget {
+    limit: u64,
+    remaining: u64,
+    reset_at: DateTime<Utc>,
+    observed_at: DateTime<Utc>,
+}
+
+impl RateLimitBudget {
+    pub fn get_limit(&self)-> u64 {
+        self.limit
+    }
+    pub fn get_remaining(&self)-> u64 {
+        self.remaining
+    }
+    pub fn get_reset_at(&self)-> DateTime<Utc> {
+        self.reset_at
+    }
+    pub fn get_observed_at(&self)-> DateTime<Utc> {
+        self.observed_at
+    }
+    pub fn is_exhausted(&self, now: DateTime<Utc>)-> bool {
+        self.remaining == 0 && self.reset_at > now
+    }
+}
+
+/// GraphQL and REST calls draw from separate budgets, keyed e.g. `api.github.com graphql`.
+fn get_budget_key(host: &str, resource: &str)-> String {
+    format!("{} {}", host, resource)
+}
+
+fn guess_resource(url: &str)-> &'static str {
+    if url.split(['?', '#']).next().unwrap_or_default().ends_with("/graphql") {
+        "graphql"
+    } else {
+        "core"
+    }
+}
+
+fn get_host(url: &str)-> String {
+    parse_url_scheme_and_host(url).map(|(_, host)| host.to_lowercase()).unwrap_or_default()
+}
+
+fn parse_header<T: std::str::FromStr>(response: &Response, name: &str)-> Option<T> {
+    response.header(name).and_then(|value| value.trim().parse().ok())
+}
+
+#[derive(Default)]
+struct GovernorState {
+    budgets: BTreeMap<String, RateLimitBudget>,
+    blocked_until: BTreeMap<String, DateTime<Utc>>,
+}
+
+/// Shared by every request an `HttpProxyAgent` makes so one exhausted budget pauses all callers.
+#[derive(Clone, Default)]
+pub struct RateLimitGovernor {
+    state: Arc<Mutex<GovernorState>>,
+}
+
+impl RateLimitGovernor {
+    /// How long a request to `url` has to wait, and why: a `Retry-After` still running or an exhausted budget.
+    pub fn get_wait(&self, url: &str, now: DateTime<Utc>)-> Option<(Duration, String)> {
+        let state = self.state.lock().unwrap();
+        let host = get_host(url);
+        let key = get_budget_key(&host, guess_resource(url));
+        let retry_after = state.blocked_until.get(&host)
+            .filter(|blocked_until| **blocked_until > now)
+            .map(|blocked_until| (*blocked_until - now, format!("{} asked gitty to slow down", host)));
+        let exhausted = state.budgets.get(&key)
+            .filter(|budget| budget.is_exhausted(now))
+            .map(|budget| (budget.reset_at - now, format!("rate limit for {} is used up", key)));
+        retry_after.into_iter().chain(exhausted).max_by_key(|(wait, _)| *wait)
+    }
+
+    pub fn wait_for_budget(&self, url: &str) {
+        if let Some((wait, reason)) = self.get_wait(url, Utc::now()) {
+            println!("The {}, waiting until {}", reason, (Utc::now() + wait).format("%H:%M:%S UTC"));
+            thread::sleep(wait.to_std().unwrap_or_default());
+        }
+    }
+
+    /// `body` is the already read body of a 403, to tell a secondary rate limit from a missing permission.
+    pub fn record_response(&self, url: &str, response: &Response, body: Option<&str>, now: DateTime<Utc>) {
+        let mut state = self.state.lock().unwrap();
+        let host = get_host(url);
+        let limit = parse_header::<u64>(response, "x-ratelimit-limit");
+        let remaining = parse_header::<u64>(response, "x-ratelimit-remaining");
+        let reset_at = parse_header::<i64>(response, "x-ratelimit-reset").and_then(|reset| Utc.timestamp_opt(reset, 0).single());
+        if let (Some(limit), Some(remaining), Some(reset_at)) = (limit, remaining, reset_at) {
+            let resource = response.header("x-ratelimit-resource").unwrap_or_else(|| guess_resource(url));
+            state.budgets.insert(get_budget_key(&host, resource), RateLimitBudget{limit, remaining, reset_at, observed_at: now});
+        }
+        let retry_after = parse_header::<i64>(response, "retry-after");
+        let wait_seconds = match response.status() {
+            403 | 429 if retry_after.is_some() => retry_after,
+            // A used up budget already holds its own resource back until `x-ratelimit-reset`.
+            _ if remaining == Some(0) => None,
+            429 => Some(SECONDARY_RATE_LIMIT_WAIT_SECONDS),
+            403 if body.is_some_and(is_rate_limit_message) => Some(SECONDARY_RATE_LIMIT_WAIT_SECONDS),
+            _ => None,
+        };
+        if let Some(wait_seconds) = wait_seconds {
+            state.blocked_until.insert(host, now + Duration::seconds(wait_seconds));
+        }
+    }
+
+    pub fn get_budgets(&self)-> BTreeMap<String, RateLimitBudget> {
+        self.state.lock().unwrap().budgets.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response(status: u16, headers: &str)-> Response {
+        format!("HTTP/1.1 {} Status\r\n{}\r\n", status, headers).parse().unwrap()
+    }
+
+    fn now()-> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 4, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn tracks_rest_and_graphql_budgets_separately() {
+        let governor = RateLimitGovernor::default();
+        let reset = (now() + Duration::minutes(10)).timestamp();
+
+        governor.record_response("https://api.github.com/graphql", &response(200, &format!(
+            "x-ratelimit-limit: 5000\r\nx-ratelimit-remaining: 0\r\nx-ratelimit-reset: {}\r\nx-ratelimit-resource: graphql\r\n", reset)), None, now());
+        governor.record_response("https://api.github.com/repos/bfrazho/gitty/collaborators", &response(200, &format!(
+            "x-ratelimit-limit: 5000\r\nx-ratelimit-remaining: 4999\r\nx-ratelimit-reset: {}\r\nx-ratelimit-resource: core\r\n", reset)), None, now());
+
+        let budgets = governor.get_budgets();
+        assert_eq!(0, budgets["api.github.com graphql"].get_remaining());
+        assert_eq!(4999, budgets["api.github.com core"].get_remaining());
+        assert_eq!(Some(Duration::minutes(10)), governor.get_wait("https://api.github.com/graphql", now()).map(|(wait, _)| wait));
+        assert_eq!(None, governor.get_wait("https://api.github.com/repos/bfrazho/gitty/collaborators", now()));
+    }
+
+    #[test]
+    fn exhausted_budget_stops_blocking_after_reset() {
+        let governor = RateLimitGovernor::default();
+        governor.record_response("https://api.github.com/user", &response(200, &format!(
+            "x-ratelimit-limit: 60\r\nx-ratelimit-remaining: 0\r\nx-ratelimit-reset: {}\r\n", now().timestamp() + 30)), None, now());
+
+        assert!(governor.get_wait("https://api.github.com/user", now()).is_some());
+        assert_eq!(None, governor.get_wait("https://api.github.com/user", now() + Duration::seconds(30)));
+    }
+
+    #[test]
+    fn honours_retry_after_for_the_whole_host() {
+        let governor = RateLimitGovernor::default();
+        governor.record_response("https://api.github.com/repos/bfrazho/gitty/comments", &response(403, "retry-after: 90\r\n"), None, now());
+
+        assert_eq!(Some(Duration::seconds(90)), governor.get_wait("https://api.github.com/graphql", now()).map(|(wait, _)| wait));
+        assert_eq!(None, governor.get_wait("https://github.some-business.com/api/graphql", now()));
+    }
+
+    #[test]
+    fn too_many_requests_without_retry_after_waits_a_minute() {
+        let governor = RateLimitGovernor::default();
+        governor.record_response("https://api.github.com/graphql", &response(429, ""), None, now());
+
+        assert_eq!(Some(Duration::seconds(60)), governor.get_wait("https://api.github.com/graphql", now()).map(|(wait, _)| wait));
+    }
+
+    #[test]
+    fn forbidden_with_a_used_up_budget_waits_until_reset() {
+        let governor = RateLimitGovernor::default();
+        governor.record_response("https://api.github.com/repos/bfrazho/gitty/comments", &response(403, &format!(
+            "x-ratelimit-limit: 5000\r\nx-ratelimit-remaining: 0\r\nx-ratelimit-reset: {}\r\nx-ratelimit-resource: core\r\n", now().timestamp() + 300)), None, now());
+
+        assert_eq!(Some(Duration::seconds(300)), governor.get_wait("https://api.github.com/repos/bfrazho/gitty/comments", now()).map(|(wait, _)| wait));
+        assert_eq!(None, governor.get_wait("https://api.github.com/graphql", now()));
+    }
+
+    #[test]
+    fn only_forbidden_responses_about_rate_limits_wait() {
+        let governor = RateLimitGovernor::default();
+        let headers = format!("x-ratelimit-limit: 5000\r\nx-ratelimit-remaining: 4000\r\nx-ratelimit-reset: {}\r\n", now().timestamp() + 300);
+        governor.record_response("https://api.github.com/repos/bfrazho/gitty/comments", &response(403, &headers),
+            Some(r#"{"message":"Resource not accessible by personal access token"}"#), now());
+        assert_eq!(None, governor.get_wait("https://api.github.com/graphql", now()));
+
+        governor.record_response("https://api.github.com/repos/bfrazho/gitty/comments", &response(403, &headers),
+            Some(r#"{"message":"You have exceeded a secondary rate limit. Please wait a few minutes before you try again."}"#), now());
+        assert_eq!(Some(Duration::seconds(60)), governor.get_wait("https://api.github.com/graphql", now()).map(|(wait, _)| wait));
+    }
+}
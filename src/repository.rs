@@ -1,90 +1,182 @@
+use std::path::Path;
+
 use gix::Url;
 
-pub trait RepositoryTrait {
-    fn get_repository_name(&self) -> String;
-    fn get_org_name(&self) -> String;
-}
-impl RepositoryTrait for Url {
-    fn get_repository_name(&self) -> String {
-        let path = self.path.to_string();
-        let split_string: Vec<&str> = path.split("/").collect();
-        let string_with_git = split_string[1];
-        string_with_git[..(string_with_git.len() - 4)].to_string()
-    }
+use crate::{config::DEFAULT_APPROVAL_MESSAGE, error::GittyError, github_app::GitHubAppAuth, remote::RemoteLocation};
 
-    fn get_org_name(&self) -> String {
-        let path = self.path.to_string();
-        let split_string: Vec<&str> = path.split("/").collect();
-        split_string[0].to_string()
-    }
+fn discover_repository(repo_path: &Path) -> Result<gix::Repository, GittyError> {
+    gix::discover(repo_path).map_err(|error| GittyError::Config(format!("{} is not inside a git repository: {}", repo_path.display(), error)))
 }
 
-pub fn get_repository_url() -> Url {
-    let repo = gix::discover(".").unwrap();
-    let remote = repo
-        .find_default_remote(gix::remote::Direction::Fetch)
-        .unwrap()
-        .unwrap();
+/// Fetch url of `remote_name`, or of the default remote when no name is given.
+pub fn get_repository_url(repo_path: &Path, remote_name: Option<&str>) -> Result<Url, GittyError> {
+    let repo = discover_repository(repo_path)?;
+    let remote = match remote_name {
+        Some(remote_name) => repo
+            .find_remote(remote_name)
+            .map_err(|error| GittyError::Config(format!("could not find remote {}: {}", remote_name, error)))?,
+        None => repo
+            .find_default_remote(gix::remote::Direction::Fetch)
+            .ok_or_else(|| GittyError::Config("repository has no remote to fetch from".to_string()))?
+            .map_err(|error| GittyError::Config(format!("could not read the default remote: {}", error)))?,
+    };
     remote
         .url(gix::remote::Direction::Fetch)
-        .unwrap()
-        .to_owned()
+        .map(|url| url.to_owned())
+        .ok_or_else(|| GittyError::Config("remote has no fetch url".to_string()))
+}
+
+/// Every remote with a fetch url, by name.
+pub fn list_remotes(repo_path: &Path) -> Result<Vec<(String, Url)>, GittyError> {
+    let repo = discover_repository(repo_path)?;
+    let mut remotes = Vec::new();
+    for name in repo.remote_names().into_iter().map(|name| name.to_string()) {
+        let remote = repo.find_remote(name.as_str())
+            .map_err(|error| GittyError::Config(format!("could not read remote {}: {}", name, error)))?;
+        if let Some(url) = remote.url(gix::remote::Direction::Fetch) {
+            remotes.push((name, url.to_owned()));
+        }
+    }
+    Ok(remotes)
+}
+
+/// Where `remote_name`, or the default remote, points on GitHub.
+pub fn get_remote_location(repo_path: &Path, remote_name: Option<&str>) -> Result<RemoteLocation, GittyError> {
+    RemoteLocation::try_from(&get_repository_url(repo_path, remote_name)?)
 }
 
-pub fn get_main_branch_name() -> String {
-    let repo = gix::discover(".").unwrap();
-    repo.branch_names().into_iter()
-        .filter(|branch| branch == &"main" || branch == &"master")
-        .last().expect("could not find main or master branch").to_string()
+/// The branch `refs/remotes/<remote>/HEAD` points at, as recorded by `git clone` or `git remote set-head`.
+pub fn get_remote_head_branch(repo_path: &Path, remote_name: Option<&str>) -> Result<Option<String>, GittyError> {
+    let repo = discover_repository(repo_path)?;
+    let remote_name = match remote_name {
+        Some(remote_name) => remote_name.to_string(),
+        None => match repo.find_default_remote(gix::remote::Direction::Fetch).and_then(Result::ok) {
+            Some(remote) => match remote.name() {
+                Some(name) => name.as_bstr().to_string(),
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        },
+    };
+    let prefix = format!("refs/remotes/{}/", remote_name);
+    let head = repo.try_find_reference(format!("{}HEAD", prefix).as_str())
+        .map_err(|error| GittyError::Config(format!("could not read {}HEAD: {}", prefix, error)))?;
+    Ok(head.as_ref()
+        .and_then(|head| head.target().try_name().map(|target| target.as_bstr().to_string()))
+        .and_then(|target| target.strip_prefix(&prefix).map(str::to_string)))
+}
+
+/// First of `candidates` that exists as a local branch.
+pub fn get_main_branch_name(repo_path: &Path, candidates: &[String]) -> Result<String, GittyError> {
+    let repo = discover_repository(repo_path)?;
+    let branch_names = repo.branch_names();
+    candidates.iter()
+        .find(|candidate| branch_names.contains(candidate.as_str()))
+        .cloned()
+        .ok_or_else(|| GittyError::Config(format!("could not find any of the branches {}", candidates.join(", "))))
+}
+
+pub const DEFAULT_MAX_HISTORY_PAGES: usize = 10;
+
+/// How requests to GitHub are authenticated.
+pub enum Auth {
+    Token(String),
+    GitHubApp(Box<GitHubAppAuth>),
+}
+
+impl Auth {
+    pub fn for_another_repository(&self)-> Auth {
+        match self {
+            Auth::Token(token) => Auth::Token(token.clone()),
+            Auth::GitHubApp(app) => Auth::GitHubApp(Box::new(app.for_another_repository())),
+        }
+    }
 }
 
 pub struct GitRepository {
-    token: String,
-    url: Url,
-    main_branch_name: String
+    auth: Auth,
+    remote: RemoteLocation,
+    main_branch_name: String,
+    max_history_pages: usize,
+    approval_message: String
 }
 
 impl GitRepository{
-    pub fn new(token: String, url: Url, main_branch_name: String)-> Self {
-        Self{token, url, main_branch_name}
+    #[cfg(test)]
+    pub fn new(token: String, remote: RemoteLocation, main_branch_name: String)-> Self {
+        Self::new_with_auth(Auth::Token(token), remote, main_branch_name)
+    }
+
+    pub fn new_with_auth(auth: Auth, remote: RemoteLocation, main_branch_name: String)-> Self {
+        Self{auth, remote, main_branch_name, max_history_pages: DEFAULT_MAX_HISTORY_PAGES, approval_message: DEFAULT_APPROVAL_MESSAGE.to_string()}
+    }
+
+    /// Another repository with the same credentials and settings.
+    pub fn with_remote(&self, remote: RemoteLocation)-> Self {
+        Self{
+            auth: self.auth.for_another_repository(),
+            remote,
+            main_branch_name: self.main_branch_name.clone(),
+            max_history_pages: self.max_history_pages,
+            approval_message: self.approval_message.clone(),
+        }
+    }
+
+    pub fn with_main_branch_name(mut self, main_branch_name: String)-> Self {
+        self.main_branch_name = main_branch_name;
+        self
+    }
+
+    pub fn with_approval_message(mut self, approval_message: &str)-> Self {
+        self.approval_message = approval_message.to_string();
+        self
+    }
+
+    /// Safety cap on how many pages of 100 commits a single poll may read.
+    pub fn with_max_history_pages(mut self, max_history_pages: usize)-> Self {
+        self.max_history_pages = max_history_pages;
+        self
     }
     
-    pub fn get_token(&self)-> &str{
-        return &self.token
+    /// For a GitHub App this refreshes the installation token when it is about to expire.
+    pub fn get_token(&self)-> Result<String, GittyError> {
+        match &self.auth {
+            Auth::Token(token) => Ok(token.clone()),
+            Auth::GitHubApp(app) => app.get_installation_token(&self.get_base_rest_url(), &self.get_org_name(), &self.get_repository_name()),
+        }
     }
-    pub fn get_bearer_token_string(&self)-> String{
-        format!("Bearer {}", self.get_token())
+    pub fn set_token(&mut self, token: String) {
+        self.auth = Auth::Token(token);
+    }
+    pub fn get_bearer_token_string(&self)-> Result<String, GittyError> {
+        Ok(format!("Bearer {}", self.get_token()?))
     }
     pub fn get_main_branch_name(&self)-> &str {
         return &self.main_branch_name
     }
+    pub fn get_max_history_pages(&self)-> usize {
+        self.max_history_pages
+    }
+    pub fn get_approval_message(&self)-> &str {
+        &self.approval_message
+    }
     
     pub fn get_graphql_url(&self)-> String{
-        let host = self.get_host();
-        if host == "github.com"{
-            format!("https://api.{}/graphql",host)
-        } else {
-            format!("https://{}/api/graphql",host)
-        }
+        self.remote.get_graphql_url()
     }
 
     pub fn get_org_name(&self)-> String{
-        return self.url.get_org_name()
+        self.remote.get_owner().to_string()
     }
     pub fn get_repository_name(&self)-> String{
-        return self.url.get_repository_name()
+        self.remote.get_repo().to_string()
     }
 
     pub fn get_base_rest_url(&self)-> String {
-        let host = self.get_host();
-        if host == "github.com" {
-            return format!("https://api.{}", host)
-        } else {
-            return format!("https://{}/api/v3", host)
-        }
+        self.remote.get_base_rest_url()
     }
     pub fn get_host(&self)-> &str{
-        return self.url.host().unwrap()
+        self.remote.get_host()
     }
 }
 
@@ -92,35 +184,16 @@ impl GitRepository{
 mod test {
     use super::*;
 
-    #[test]
-    fn retrieve_repo_name_from_url() {
-        assert_eq!(
-            "gitty",
-            Url::try_from("git@github.com:bfrazho/gitty.git")
-                .unwrap()
-                .get_repository_name()
-        );
-    }
-    #[test]
-    fn retrieve_org_name_from_url() {
-        assert_eq!(
-            "bfrazho",
-            Url::try_from("git@github.com:bfrazho/gitty.git")
-                .unwrap()
-                .get_org_name()
-        );
-    }
-
     #[test]
     fn get_repository_url_gets_current() {
         assert!(vec!["git@github.com:bfrazho/gitty.git".to_string(), "https://github.com/bfrazho/gitty".to_string()]
-            .contains(&get_repository_url().to_bstring().to_string()))
+            .contains(&get_repository_url(Path::new("."), None).unwrap().to_bstring().to_string()))
     }
 
     #[test]
     fn can_get_graphql_url_for_standard_git(){
         let repository = {
-            let token = "the token".to_string();let url = Url::try_from("git@github.com:bfrazho/gitty.git").unwrap();
+            let token = "the token".to_string();let url = RemoteLocation::parse("git@github.com:bfrazho/gitty.git").unwrap();
             GitRepository::new(token, url, "".to_string())
         };
         assert_eq!("https://api.github.com/graphql", repository.get_graphql_url());
@@ -129,7 +202,7 @@ mod test {
     #[test]
     fn can_get_graphql_url_for_enterprise_git(){
         let repository = {
-            let token = "the token".to_string();let url = Url::try_from("git@github.some-business.com:bfrazho/gitty.git").unwrap();
+            let token = "the token".to_string();let url = RemoteLocation::parse("git@github.some-business.com:bfrazho/gitty.git").unwrap();
             GitRepository::new(token, url, "".to_string())
         };
         assert_eq!("https://github.some-business.com/api/graphql", repository.get_graphql_url());
@@ -138,7 +211,7 @@ mod test {
     #[test]
     fn can_get_rest_url_for_standard_git(){
         let repository = {
-            let token = "the token".to_string();let url = Url::try_from("git@github.com:bfrazho/gitty.git").unwrap();
+            let token = "the token".to_string();let url = RemoteLocation::parse("git@github.com:bfrazho/gitty.git").unwrap();
             GitRepository::new(token, url, "".to_string())
         };
         assert_eq!("https://api.github.com", repository.get_base_rest_url());
@@ -147,7 +220,7 @@ mod test {
     #[test]
     fn can_get_rest_url_for_enterprise_git(){
         let repository = {
-            let token = "the token".to_string();let url = Url::try_from("git@github.some-business.com:bfrazho/gitty.git").unwrap();
+            let token = "the token".to_string();let url = RemoteLocation::parse("git@github.some-business.com:bfrazho/gitty.git").unwrap();
             GitRepository::new(token, url, "".to_string())
         };
         assert_eq!("https://github.some-business.com/api/v3", repository.get_base_rest_url());
@@ -156,17 +229,52 @@ mod test {
     #[test]
     fn can_create_git_repository() {
         let repository = {
-            let token = "the token".to_string();let url = Url::try_from("git@github.com:bfrazho/gitty.git").unwrap();
+            let token = "the token".to_string();let url = RemoteLocation::parse("git@github.com:bfrazho/gitty.git").unwrap();
             GitRepository::new(token, url, "".to_string())
         };
 
-        assert_eq!("the token", repository.get_token());
+        assert_eq!("the token", repository.get_token().unwrap());
         assert_eq!("bfrazho".to_string(), repository.get_org_name());
         assert_eq!("github.com", repository.get_host());
         assert_eq!("gitty", repository.get_repository_name());
+        assert_eq!(DEFAULT_MAX_HISTORY_PAGES, repository.get_max_history_pages());
+        assert_eq!("I approve this", repository.get_approval_message());
     }
+
+    #[test]
+    fn another_remote_keeps_credentials_and_settings() {
+        let repository = GitRepository::new("the token".to_string(), RemoteLocation::parse("git@github.com:bfrazho/gitty.git").unwrap(), "main".to_string())
+            .with_max_history_pages(3)
+            .with_approval_message("lgtm");
+
+        let upstream = repository.with_remote(RemoteLocation::parse("git@github.com:upstream-org/gitty.git").unwrap());
+
+        assert_eq!("upstream-org", upstream.get_org_name());
+        assert_eq!("the token", upstream.get_token().unwrap());
+        assert_eq!(("main", 3, "lgtm"), (upstream.get_main_branch_name(), upstream.get_max_history_pages(), upstream.get_approval_message()));
+    }
+
+    #[test]
+    fn can_override_max_history_pages() {
+        let repository = GitRepository::new("the token".to_string(), RemoteLocation::parse("git@github.com:bfrazho/gitty.git").unwrap(), "".to_string())
+            .with_max_history_pages(3);
+        assert_eq!(3, repository.get_max_history_pages());
+    }
+    #[test]
+    fn can_get_branch_remote_head_points_at() {
+        let repo_path = std::env::temp_dir().join("gitty_can_get_branch_remote_head_points_at");
+        std::fs::remove_dir_all(&repo_path).unwrap_or_default();
+        gix::init(&repo_path).unwrap();
+        std::fs::create_dir_all(repo_path.join(".git/refs/remotes/origin")).unwrap();
+        std::fs::write(repo_path.join(".git/refs/remotes/origin/HEAD"), "ref: refs/remotes/origin/develop\n").unwrap();
+
+        assert_eq!(Some("develop".to_string()), get_remote_head_branch(&repo_path, Some("origin")).unwrap());
+        assert_eq!(None, get_remote_head_branch(&repo_path, Some("upstream")).unwrap());
+        assert_eq!(None, get_remote_head_branch(&repo_path, None).unwrap());
+    }
+
     #[test]
     fn can_get_main_branch_name() {
-        assert_eq!("main", get_main_branch_name())
+        assert_eq!("main", get_main_branch_name(Path::new("."), &["main".to_string(), "master".to_string()]).unwrap())
     }
 }
@@ -0,0 +1,127 @@
+use std::{collections::hash_map::RandomState, hash::{BuildHasher, Hasher}, time::Duration};
+
+use ureq::{Error, ErrorKind};
+
+pub const DEFAULT_RETRY_MAX_ATTEMPTS: u32 = 3;
+pub const DEFAULT_RETRY_INITIAL_BACKOFF_MS: u64 = 500;
+pub const DEFAULT_RETRY_MAX_BACKOFF_MS: u64 = 10_000;
+pub const DEFAULT_RETRY_JITTER_PERCENT: u32 = 20;
+pub const DEFAULT_REQUEST_TIMEOUT_SECONDS: u64 = 30;
+
+/// How often and how patiently a single request is sent again after a transient failure.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter_percent: u32,
+    timeout: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_RETRY_MAX_ATTEMPTS,
+            Duration::from_millis(DEFAULT_RETRY_INITIAL_BACKOFF_MS),
+            Duration::from_millis(DEFAULT_RETRY_MAX_BACKOFF_MS),
+            DEFAULT_RETRY_JITTER_PERCENT,
+            Duration::from_secs(DEFAULT_REQUEST_TIMEOUT_SECONDS),
+        )
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, initial_backoff: Duration, max_backoff: Duration, jitter_percent: u32, timeout: Duration)-> Self {
+        Self{max_attempts: max_attempts.max(1), initial_backoff, max_backoff, jitter_percent: jitter_percent.min(100), timeout}
+    }
+
+    pub fn get_max_attempts(&self)-> u32 {
+        self.max_attempts
+    }
+
+    pub fn get_timeout(&self)-> Duration {
+        self.timeout
+    }
+
+    pub fn get_jitter_percent(&self)-> u32 {
+        self.jitter_percent
+    }
+
+    pub fn get_initial_backoff(&self)-> Duration {
+        self.initial_backoff
+    }
+
+    pub fn get_max_backoff(&self)-> Duration {
+        self.max_backoff
+    }
+
+    /// Wait before the `retry`th resend: doubling from `initial_backoff` up to `max_backoff`, then moved
+    /// up or down by as much as `jitter_percent` so clients that failed together do not retry together.
+    /// `random` is in `0.0..1.0`.
+    pub fn get_delay(&self, retry: u32, random: f64)-> Duration {
+        let backoff = self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_backoff);
+        let jitter = f64::from(self.jitter_percent) / 100.0 * (2.0 * random - 1.0);
+        backoff.mul_f64(1.0 + jitter)
+    }
+
+    pub fn get_random_delay(&self, retry: u32)-> Duration {
+        self.get_delay(retry, random_fraction())
+    }
+}
+
+fn random_fraction()-> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// 5xx and 429 answers and dropped connections are worth another try. Unless the request is idempotent,
+/// only failures that happen before anything reached the server are.
+pub fn is_retryable(error: &Error, idempotent: bool)-> bool {
+    match error {
+        Error::Status(status, _) => idempotent && matches!(status, 429 | 500 | 502 | 503 | 504),
+        Error::Transport(transport) => match transport.kind() {
+            ErrorKind::Dns | ErrorKind::ConnectionFailed | ErrorKind::ProxyConnect => true,
+            ErrorKind::Io | ErrorKind::BadStatus | ErrorKind::BadHeader => idempotent,
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn status_error(status: u16)-> Error {
+        Error::Status(status, format!("HTTP/1.1 {} Status\r\n\r\n", status).parse().unwrap())
+    }
+
+    #[test]
+    fn delay_doubles_until_max_backoff() {
+        let policy = RetryPolicy::new(5, Duration::from_secs(1), Duration::from_secs(5), 0, Duration::from_secs(30));
+        assert_eq!(Duration::from_secs(1), policy.get_delay(1, 0.5));
+        assert_eq!(Duration::from_secs(2), policy.get_delay(2, 0.5));
+        assert_eq!(Duration::from_secs(4), policy.get_delay(3, 0.5));
+        assert_eq!(Duration::from_secs(5), policy.get_delay(4, 0.5));
+    }
+
+    #[test]
+    fn jitter_stays_within_its_percentage() {
+        let policy = RetryPolicy::new(3, Duration::from_secs(10), Duration::from_secs(60), 20, Duration::from_secs(30));
+        assert_eq!(Duration::from_secs(8), policy.get_delay(1, 0.0));
+        assert_eq!(Duration::from_secs(10), policy.get_delay(1, 0.5));
+        assert!(policy.get_delay(1, 0.999) < Duration::from_secs(12));
+        let random_delay = policy.get_random_delay(1);
+        assert!(random_delay >= Duration::from_secs(8) && random_delay <= Duration::from_secs(12));
+    }
+
+    #[test]
+    fn server_errors_are_only_retried_for_idempotent_requests() {
+        assert!(is_retryable(&status_error(502), true));
+        assert!(!is_retryable(&status_error(502), false));
+        assert!(!is_retryable(&status_error(404), true));
+        assert!(!is_retryable(&status_error(401), true));
+    }
+}
@@ -0,0 +1,277 @@
+use std::{collections::{BTreeMap, BTreeSet}, fs, io::ErrorKind, path::{Path, PathBuf}};
+
+use chrono::{DateTime, Local};
+use serde::{Serialize, Deserialize};
+
+use crate::{config::get_state_dir, error::GittyError, profile::{read_with_legacy_fallback, replace_private_file}, rate_limit::RateLimitBudget, remote::RemoteLocation, repository::GitRepository};
+
+const LEGACY_STATE_FILE_NAME: &str = "gitty_state.json";
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct BranchState {
+    last_seen_oid: Option<String>,
+    last_polled_at: Option<DateTime<Local>>,
+    approved_oids: BTreeSet<String>,
+}
+
+impl BranchState {
+    pub fn get_last_seen_oid(&self)-> Option<&str> {
+        self.last_seen_oid.as_deref()
+    }
+
+    pub fn get_last_polled_at(&self)-> Option<DateTime<Local>> {
+        self.last_polled_at
+    }
+
+    /// Where the next poll should start looking from: the last time we polled,
+    /// or `now` when this branch has never been watched before.
+    pub fn get_resume_timestamp(&self, now: DateTime<Local>)-> DateTime<Local> {
+        self.last_polled_at.unwrap_or(now)
+    }
+
+    pub fn is_approved(&self, oid: &str)-> bool {
+        self.approved_oids.contains(oid)
+    }
+
+    pub fn get_approved_oids(&self)-> &BTreeSet<String> {
+        &self.approved_oids
+    }
+
+    pub fn mark_approved(&mut self, oid: &str) {
+        self.approved_oids.insert(oid.to_string());
+    }
+
+    pub fn record_poll(&mut self, polled_at: DateTime<Local>, last_seen_oid: Option<String>) {
+        self.last_polled_at = Some(polled_at);
+        if last_seen_oid.is_some() {
+            self.last_seen_oid = last_seen_oid;
+        }
+    }
+
+    /// Folds in what another gitty process saved: approvals from both are kept, and the
+    /// position of whichever polled last wins.
+    fn merge(&mut self, other: &BranchState) {
+        self.approved_oids.extend(other.approved_oids.iter().cloned());
+        if other.last_polled_at > self.last_polled_at {
+            self.last_polled_at = other.last_polled_at;
+            self.last_seen_oid = other.last_seen_oid.clone().or(self.last_seen_oid.take());
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Serialize, Deserialize, Default, Clone)]
+pub struct WatcherState {
+    session_started_at: Option<DateTime<Local>>,
+    branches: BTreeMap<String, BranchState>,
+    #[serde(default)]
+    rate_limits: BTreeMap<String, RateLimitBudget>,
+}
+
+impl WatcherState {
+    pub fn start_session(&mut self, now: DateTime<Local>) {
+        self.session_started_at = Some(now);
+    }
+
+    pub fn get_session_started_at(&self)-> Option<DateTime<Local>> {
+        self.session_started_at
+    }
+
+    pub fn get_branch_state(&self, key: &str)-> Option<&BranchState> {
+        self.branches.get(key)
+    }
+
+    pub fn get_branch_states(&self)-> &BTreeMap<String, BranchState> {
+        &self.branches
+    }
+
+    pub fn get_branch_state_mut(&mut self, key: &str)-> &mut BranchState {
+        self.branches.entry(key.to_string()).or_default()
+    }
+
+    /// Last budgets the watcher saw, so `gitty status` can show them from another process.
+    pub fn set_rate_limits(&mut self, rate_limits: BTreeMap<String, RateLimitBudget>) {
+        self.rate_limits = rate_limits;
+    }
+
+    pub fn get_rate_limits(&self)-> &BTreeMap<String, RateLimitBudget> {
+        &self.rate_limits
+    }
+
+    /// Saves the state, keeping anything another gitty process saved since this one loaded it.
+    pub fn save(&mut self)-> Result<(), GittyError> {
+        self.save_to(&get_state_path()?)
+    }
+
+    fn save_to(&mut self, path: &Path)-> Result<(), GittyError> {
+        let saved = load_state_from(path)?;
+        self.merge(&saved);
+        replace_private_file(path, &serde_json::to_string_pretty(self)?)
+    }
+
+    fn merge(&mut self, other: &WatcherState) {
+        self.session_started_at = self.session_started_at.max(other.session_started_at);
+        for (key, branch_state) in other.branches.iter() {
+            self.get_branch_state_mut(key).merge(branch_state);
+        }
+        if self.rate_limits.is_empty() {
+            self.rate_limits = other.rate_limits.clone();
+        }
+    }
+}
+
+impl GitRepository {
+    pub fn get_state_key(&self)-> String {
+        self.get_branch_state_key(self.get_main_branch_name())
+    }
+
+    pub fn get_branch_state_key(&self, branch: &str)-> String {
+        format!("{}/{}/{}@{}", self.get_host(), self.get_org_name(), self.get_repository_name(), branch)
+    }
+}
+
+/// What the state key of every branch of the repository at `remote` starts with.
+pub fn get_repository_state_key_prefix(remote: &RemoteLocation)-> String {
+    format!("{}/{}/{}@", remote.get_host(), remote.get_owner(), remote.get_repo())
+}
+
+fn get_state_path()-> Result<PathBuf, GittyError> {
+    get_state_dir()
+        .map(|dir| dir.join("state.json"))
+        .ok_or_else(|| GittyError::Config("could not find a directory for watcher state, set XDG_STATE_HOME".to_string()))
+}
+
+/// The saved watcher state, or a fresh one the first time gitty runs. A state file that cannot be read is an
+/// error rather than a fresh start, since it is the only record of which commits were already approved.
+pub fn load_state()-> Result<WatcherState, GittyError> {
+    let path = get_state_path()?;
+    match read_with_legacy_fallback(&path, LEGACY_STATE_FILE_NAME) {
+        Some(contents) => parse_state(&path, &contents),
+        None => load_state_from(&path),
+    }
+}
+
+fn load_state_from(path: &Path)-> Result<WatcherState, GittyError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => parse_state(path, &contents),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(WatcherState::default()),
+        Err(error) => Err(GittyError::Config(format!("failed to read watcher state from {}: {}", path.display(), error))),
+    }
+}
+
+fn parse_state(path: &Path, contents: &str)-> Result<WatcherState, GittyError> {
+    serde_json::from_str(contents).map_err(|error| GittyError::Config(format!(
+        "watcher state in {} is unreadable ({}), fix or remove it; it records which commits were already approved",
+        path.display(), error)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use chrono::{Duration, Local};
+    use crate::remote::RemoteLocation;
+
+    use super::*;
+
+    #[test]
+    fn resume_timestamp_defaults_to_now_for_new_branch() {
+        let now = Local::now();
+        assert_eq!(now, BranchState::default().get_resume_timestamp(now));
+    }
+
+    #[test]
+    fn resume_timestamp_uses_last_poll() {
+        let now = Local::now();
+        let last_poll = now - Duration::hours(3);
+        let mut branch_state = BranchState::default();
+        branch_state.record_poll(last_poll, Some("abc".to_string()));
+
+        assert_eq!(last_poll, branch_state.get_resume_timestamp(now));
+        assert_eq!(Some("abc"), branch_state.get_last_seen_oid());
+    }
+
+    #[test]
+    fn record_poll_keeps_last_seen_oid_when_nothing_new() {
+        let mut branch_state = BranchState::default();
+        branch_state.record_poll(Local::now(), Some("abc".to_string()));
+        branch_state.record_poll(Local::now(), None);
+
+        assert_eq!(Some("abc"), branch_state.get_last_seen_oid());
+    }
+
+    #[test]
+    fn can_mark_commits_approved() {
+        let mut branch_state = BranchState::default();
+        branch_state.mark_approved("abc");
+
+        assert!(branch_state.is_approved("abc"));
+        assert!(!branch_state.is_approved("def"));
+    }
+
+    #[test]
+    fn state_key_includes_host_repo_and_branch() {
+        let repository = GitRepository::new("token".to_string(), RemoteLocation::parse("git@github.com:bfrazho/gitty.git").unwrap(), "main".to_string());
+        assert_eq!("github.com/bfrazho/gitty@main", repository.get_state_key());
+        assert_eq!("github.com/bfrazho/gitty@", get_repository_state_key_prefix(&RemoteLocation::parse("git@github.com:bfrazho/gitty.git").unwrap()));
+    }
+
+    fn temporary_state_path(test_name: &str)-> PathBuf {
+        let dir = env::temp_dir().join(test_name);
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        dir.join("state.json")
+    }
+
+    #[test]
+    fn can_save_and_load_state() {
+        let path_to_state = temporary_state_path("gitty_can_save_and_load_state");
+
+        let mut state = WatcherState::default();
+        state.start_session(Local::now());
+        state.get_branch_state_mut("github.com/bfrazho/gitty@main").mark_approved("abc");
+        state.save_to(&path_to_state).unwrap();
+
+        assert_eq!(state, load_state_from(&path_to_state).unwrap());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(0o600, fs::metadata(&path_to_state).unwrap().permissions().mode() & 0o777);
+        }
+    }
+
+    #[test]
+    fn load_state_defaults_when_missing() {
+        let path_to_state = temporary_state_path("gitty_load_state_defaults_when_missing");
+        assert_eq!(WatcherState::default(), load_state_from(&path_to_state).unwrap());
+    }
+
+    #[test]
+    fn load_state_rejects_unreadable_state() {
+        let path_to_state = temporary_state_path("gitty_load_state_rejects_unreadable_state");
+        fs::create_dir_all(path_to_state.parent().unwrap()).unwrap();
+        fs::write(&path_to_state, "{\"branches\": ").unwrap();
+
+        assert!(matches!(load_state_from(&path_to_state), Err(GittyError::Config(_))));
+    }
+
+    #[test]
+    fn save_keeps_what_another_process_saved() {
+        let path_to_state = temporary_state_path("gitty_save_keeps_what_another_process_saved");
+        let earlier = Local::now() - Duration::minutes(5);
+        let mut watcher = WatcherState::default();
+        let mut approve_command = WatcherState::default();
+
+        watcher.get_branch_state_mut("github.com/bfrazho/gitty@main").record_poll(earlier, Some("old".to_string()));
+        approve_command.get_branch_state_mut("github.com/bfrazho/gitty@main").mark_approved("abc");
+        approve_command.get_branch_state_mut("github.com/bfrazho/gitty@develop").mark_approved("def");
+        approve_command.get_branch_state_mut("github.com/bfrazho/gitty@main").record_poll(Local::now(), Some("new".to_string()));
+        approve_command.save_to(&path_to_state).unwrap();
+        watcher.get_branch_state_mut("github.com/bfrazho/gitty@main").mark_approved("ghi");
+        watcher.save_to(&path_to_state).unwrap();
+
+        let saved = load_state_from(&path_to_state).unwrap();
+        let main = saved.get_branch_state("github.com/bfrazho/gitty@main").unwrap();
+        assert!(main.is_approved("abc") && main.is_approved("ghi"));
+        assert_eq!(Some("new"), main.get_last_seen_oid());
+        assert!(saved.get_branch_state("github.com/bfrazho/gitty@develop").unwrap().is_approved("def"));
+    }
+}
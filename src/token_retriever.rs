@@ -1,87 +1,315 @@
-use std::{env::current_exe, fs, path::PathBuf};
+use std::{collections::BTreeMap, fmt::Display, fs, io::Write, path::PathBuf, process::{Child, Command, Output, Stdio}, thread, time::{Duration, Instant}};
 
-use crate::user_input_generator::TextInputGeneratorTrait;
+use serde::Deserialize;
 
+use crate::{credential_store::{CredentialStore, GITHUB_TOKEN_KEY}, error::GittyError, user_input_generator::TextInputGeneratorTrait};
 
+/// A credential helper still waiting on something gitty cannot see, e.g. a locked keychain, is taken as having no token.
+const GIT_CREDENTIAL_TIMEOUT: Duration = Duration::from_secs(10);
 
+/// Where the token gitty ended up using came from.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum TokenSource {
+    Environment(String),
+    CredentialStore(String),
+    GhHostsFile(PathBuf),
+    GitCredentialHelper,
+    Netrc(PathBuf),
+    Prompt,
+}
 
+impl Display for TokenSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenSource::Environment(name) => write!(f, "the {} environment variable", name),
+            TokenSource::CredentialStore(description) => write!(f, "{}", description),
+            TokenSource::GhHostsFile(path) => write!(f, "gh's {}", path.display()),
+            TokenSource::GitCredentialHelper => write!(f, "git credential fill"),
+            TokenSource::Netrc(path) => write!(f, "{}", path.display()),
+            TokenSource::Prompt => write!(f, "the prompt"),
+        }
+    }
+}
 
-fn get_github_token_path()-> PathBuf {
-    let mut path_to_github_token = current_exe().unwrap().parent().unwrap().to_owned();
-    path_to_github_token.push("github_token");
-    path_to_github_token
+/// Looks for a token in the environment, gitty's own credential store, gh's `hosts.yml`,
+/// `git credential fill` and `.netrc`, in that order, and only prompts when none of them has one.
+pub fn resolve_github_token(
+    host: &str,
+    repository_path: &str,
+    credential_store: &dyn CredentialStore,
+    user_input_generator: &mut dyn TextInputGeneratorTrait,
+    get_var: impl Fn(&str)-> Option<String>,
+) -> Result<(String, TokenSource), GittyError> {
+    if let Some(found) = get_token_from_environment(host, &get_var) {
+        return Ok(found);
+    }
+    if let Some(github_token) = credential_store.get(GITHUB_TOKEN_KEY)? {
+        return Ok((github_token, TokenSource::CredentialStore(credential_store.describe())));
+    }
+    let found = get_token_from_gh_hosts_file(host, &get_var)
+        .or_else(|| get_token_from_git_credential_helper(host, repository_path))
+        .or_else(|| get_token_from_netrc(host, &get_var));
+    match found {
+        Some(found) => Ok(found),
+        None => Ok((prompt_for_github_token_and_save(credential_store, user_input_generator)?, TokenSource::Prompt)),
+    }
 }
 
-pub fn get_github_token_and_prompt_if_not_found(
+pub fn prompt_for_github_token_and_save(
+    credential_store: &dyn CredentialStore,
     user_input_generator: &mut dyn TextInputGeneratorTrait,
-) -> String {
-    let path_to_github_token = get_github_token_path();
-    match fs::read_to_string(path_to_github_token.clone()) {
-        Ok(github_token) => github_token,
-        Err(_) => {
-            let github_token = user_input_generator
-                .get_password_input("Please enter your github token")
-                .unwrap();
-            fs::write(path_to_github_token, github_token.clone()).expect("failed to write token to file");
-            github_token
+) -> Result<String, GittyError> {
+    let github_token = user_input_generator
+        .get_password_input("Please enter your github token")
+        .ok_or_else(|| GittyError::Auth("no github token was entered".to_string()))?;
+    credential_store.set(GITHUB_TOKEN_KEY, &github_token)?;
+    Ok(github_token)
+}
+
+/// Same rules as gh: the enterprise variable is only sent to enterprise hosts so a github.com token never leaks to them.
+fn get_token_from_environment(host: &str, get_var: impl Fn(&str)-> Option<String>)-> Option<(String, TokenSource)> {
+    let names: &[&str] = if host.eq_ignore_ascii_case("github.com") {
+        &["GITHUB_TOKEN", "GH_TOKEN"]
+    } else {
+        &["GH_ENTERPRISE_TOKEN", "GITHUB_ENTERPRISE_TOKEN"]
+    };
+    names.iter().find_map(|name| {
+        get_var(name)
+            .filter(|token| !token.is_empty())
+            .map(|token| (token, TokenSource::Environment(name.to_string())))
+    })
+}
+
+#[derive(Deserialize)]
+struct GhHost {
+    oauth_token: Option<String>,
+}
+
+fn get_gh_hosts_path(get_var: impl Fn(&str)-> Option<String>)-> Option<PathBuf> {
+    let config_dir = get_var("GH_CONFIG_DIR").map(PathBuf::from)
+        .or_else(|| get_var("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()).map(|dir| PathBuf::from(dir).join("gh")))
+        .or_else(|| get_var("HOME").map(|home| PathBuf::from(home).join(".config").join("gh")))
+        .or_else(|| get_var("APPDATA").map(|dir| PathBuf::from(dir).join("GitHub CLI")))?;
+    Some(config_dir.join("hosts.yml"))
+}
+
+fn parse_gh_hosts(contents: &str, host: &str)-> Option<String> {
+    let hosts = serde_yaml::from_str::<BTreeMap<String, GhHost>>(contents).ok()?;
+    hosts.into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(host))
+        .and_then(|(_, gh_host)| gh_host.oauth_token)
+        .filter(|token| !token.is_empty())
+}
+
+/// Only finds tokens gh wrote in plain text; ones kept in the system keyring are left to `git credential fill`.
+fn get_token_from_gh_hosts_file(host: &str, get_var: impl Fn(&str)-> Option<String>)-> Option<(String, TokenSource)> {
+    let path = get_gh_hosts_path(get_var)?;
+    let token = parse_gh_hosts(&fs::read_to_string(&path).ok()?, host)?;
+    Some((token, TokenSource::GhHostsFile(path)))
+}
+
+fn parse_git_credential_output(output: &str)-> Option<String> {
+    output.lines()
+        .find_map(|line| line.strip_prefix("password="))
+        .filter(|password| !password.is_empty())
+        .map(|password| password.to_string())
+}
+
+/// Asks git's configured credential helpers, with prompting turned off so nothing is typed into git instead of gitty.
+fn get_token_from_git_credential_helper(host: &str, repository_path: &str)-> Option<(String, TokenSource)> {
+    let mut child = Command::new("git")
+        .args(["credential", "fill"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GIT_ASKPASS", "")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    child.stdin.take()?
+        .write_all(format!("protocol=https\nhost={}\npath={}\n\n", host, repository_path).as_bytes())
+        .ok()?;
+    let Some(output) = wait_with_timeout(child, GIT_CREDENTIAL_TIMEOUT) else {
+        eprintln!("git credential fill did not answer within {}s, skipping it", GIT_CREDENTIAL_TIMEOUT.as_secs());
+        return None;
+    };
+    if !output.status.success() {
+        return None;
+    }
+    let token = parse_git_credential_output(&String::from_utf8_lossy(&output.stdout))?;
+    Some((token, TokenSource::GitCredentialHelper))
+}
+
+/// Kills `child` if it has not exited by `timeout`.
+fn wait_with_timeout(mut child: Child, timeout: Duration)-> Option<Output> {
+    let deadline = Instant::now() + timeout;
+    while child.try_wait().ok()?.is_none() {
+        if Instant::now() >= deadline {
+            child.kill().unwrap_or_default();
+            child.wait().unwrap_or_default();
+            return None;
         }
+        thread::sleep(Duration::from_millis(20));
     }
+    child.wait_with_output().ok()
 }
 
+/// The password of the `machine` entry for `host`. `default` entries are ignored so an unrelated password is never sent to GitHub.
+fn parse_netrc(contents: &str, host: &str)-> Option<String> {
+    let mut tokens = contents.split_whitespace();
+    let mut in_matching_machine = false;
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => in_matching_machine = tokens.next().is_some_and(|machine| machine.eq_ignore_ascii_case(host)),
+            "default" => in_matching_machine = false,
+            "password" => match tokens.next() {
+                Some(password) if in_matching_machine => return Some(password.to_string()),
+                _ => {},
+            },
+            "login" | "account" => { tokens.next(); },
+            _ => {},
+        }
+    }
+    None
+}
+
+fn get_token_from_netrc(host: &str, get_var: impl Fn(&str)-> Option<String>)-> Option<(String, TokenSource)> {
+    let file_name = if cfg!(windows) { "_netrc" } else { ".netrc" };
+    let path = get_var("NETRC").map(PathBuf::from)
+        .or_else(|| get_var("HOME").or_else(|| get_var("USERPROFILE")).map(|home| PathBuf::from(home).join(file_name)))?;
+    let token = parse_netrc(&fs::read_to_string(&path).ok()?, host)?;
+    Some((token, TokenSource::Netrc(path)))
+}
 
 #[cfg(test)]
 mod test {
-    use std::fs;
-
-    use serial_test::serial;
+    use std::{collections::HashMap, fs};
 
-    use crate::{token_retriever::get_github_token_path, user_input_generator::testing::MockTextInputGenerator};
+    use crate::{credential_store::{CredentialStore, PlaintextCredentialStore}, profile::testing::temporary_profile, user_input_generator::testing::MockTextInputGenerator};
 
-    use super::get_github_token_and_prompt_if_not_found;
+    use super::*;
 
 
     #[test]
-    #[serial]
     fn handle_when_token_not_saved() {
-        let path_to_github_token = get_github_token_path();
-        fs::remove_file(path_to_github_token.clone()).unwrap_or_default();
+        let profile = temporary_profile("handle_when_token_not_saved");
+        let credential_store = PlaintextCredentialStore::new(profile.get_dir().to_path_buf());
 
         let mut user_input_generator =
             MockTextInputGenerator::new(vec!["github_token".to_string()]);
 
 
-        let github_token = get_github_token_and_prompt_if_not_found(&mut user_input_generator);
+        let (github_token, source) = resolve_github_token("example.invalid", "org/repo.git", &credential_store, &mut user_input_generator, |_| None).unwrap();
 
         assert_eq!(
             "github_token".to_string(),
             github_token
         );
+        assert_eq!(TokenSource::Prompt, source);
         assert_eq!(
             "github_token",
-            fs::read_to_string(path_to_github_token.clone()).unwrap()
+            fs::read_to_string(profile.get_dir().join("github_token")).unwrap()
         );
-        fs::remove_file(path_to_github_token).expect("failed to delete token");
     }
 
     #[test]
-    #[serial]
     fn handle_when_token_already_exists() {
-        let path_to_github_token = get_github_token_path();
-        fs::remove_file(path_to_github_token.clone()).unwrap_or_default();
-        fs::write(path_to_github_token.clone(), "existing_github_token").expect("failed to create token");
+        let profile = temporary_profile("handle_when_token_already_exists");
+        let credential_store = PlaintextCredentialStore::new(profile.get_dir().to_path_buf());
+        credential_store.set("github_token", "existing_github_token").expect("failed to create token");
         let mut user_input_generator =
             MockTextInputGenerator::new(Vec::new());
             
-        let github_token = get_github_token_and_prompt_if_not_found(&mut user_input_generator);
+        let (github_token, source) = resolve_github_token("example.invalid", "org/repo.git", &credential_store, &mut user_input_generator, |_| None).unwrap();
 
         assert_eq!(
             "existing_github_token".to_string(),
             github_token
         );
+        assert_eq!(TokenSource::CredentialStore(credential_store.describe()), source);
         assert_eq!(
             "existing_github_token",
-            fs::read_to_string(path_to_github_token.clone()).unwrap()
+            fs::read_to_string(profile.get_dir().join("github_token")).unwrap()
         );
-        fs::remove_file(path_to_github_token).expect("failed to delete token");
+    }
+
+    #[test]
+    fn prompting_replaces_existing_token() {
+        let profile = temporary_profile("prompting_replaces_existing_token");
+        let credential_store = PlaintextCredentialStore::new(profile.get_dir().to_path_buf());
+        credential_store.set("github_token", "existing_github_token").expect("failed to create token");
+        let mut user_input_generator =
+            MockTextInputGenerator::new(vec!["new_github_token".to_string()]);
+
+        let github_token = prompt_for_github_token_and_save(&credential_store, &mut user_input_generator).unwrap();
+
+        assert_eq!("new_github_token", github_token);
+        assert_eq!(
+            "new_github_token",
+            fs::read_to_string(profile.get_dir().join("github_token")).unwrap()
+        );
+    }
+
+    #[test]
+    fn environment_token_wins_over_stored_token() {
+        let profile = temporary_profile("environment_token_wins_over_stored_token");
+        let credential_store = PlaintextCredentialStore::new(profile.get_dir().to_path_buf());
+        credential_store.set("github_token", "existing_github_token").expect("failed to create token");
+        let vars = HashMap::from([("GH_TOKEN", "env_token")]);
+
+        let (github_token, source) = resolve_github_token("github.com", "org/repo.git", &credential_store, &mut MockTextInputGenerator::new(Vec::new()),
+            |name| vars.get(name).map(|value| value.to_string())).unwrap();
+
+        assert_eq!("env_token", github_token);
+        assert_eq!(TokenSource::Environment("GH_TOKEN".to_string()), source);
+    }
+
+    #[test]
+    fn enterprise_hosts_only_use_enterprise_variables() {
+        let vars = HashMap::from([("GITHUB_TOKEN", "dotcom_token"), ("GH_ENTERPRISE_TOKEN", "enterprise_token")]);
+        let get_var = |name: &str| vars.get(name).map(|value| value.to_string());
+
+        assert_eq!(Some("dotcom_token".to_string()), get_token_from_environment("github.com", get_var).map(|(token, _)| token));
+        assert_eq!(Some("enterprise_token".to_string()), get_token_from_environment("github.some-business.com", get_var).map(|(token, _)| token));
+    }
+
+    #[test]
+    fn can_read_token_from_gh_hosts() {
+        let contents = "github.com:\n    oauth_token: gho_dotcom\n    user: someone\n    git_protocol: https\ngithub.some-business.com:\n    user: someone\n";
+
+        assert_eq!(Some("gho_dotcom".to_string()), parse_gh_hosts(contents, "github.com"));
+        assert_eq!(None, parse_gh_hosts(contents, "github.some-business.com"));
+        assert_eq!(None, parse_gh_hosts(contents, "other.host"));
+    }
+
+    #[test]
+    fn gh_hosts_path_prefers_gh_config_dir() {
+        let vars = HashMap::from([("GH_CONFIG_DIR", "/gh"), ("HOME", "/home/someone")]);
+        assert_eq!(Some(PathBuf::from("/gh/hosts.yml")), get_gh_hosts_path(|name| vars.get(name).map(|value| value.to_string())));
+        assert_eq!(Some(PathBuf::from("/home/someone/.config/gh/hosts.yml")), get_gh_hosts_path(|name| (name == "HOME").then(|| "/home/someone".to_string())));
+    }
+
+    #[test]
+    fn can_read_password_from_git_credential_output() {
+        assert_eq!(Some("ghp_secret".to_string()), parse_git_credential_output("protocol=https\nhost=github.com\nusername=someone\npassword=ghp_secret\n"));
+        assert_eq!(None, parse_git_credential_output("protocol=https\nhost=github.com\n"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn credential_helpers_that_hang_give_no_answer() {
+        let child = Command::new("sleep").arg("5").stdout(Stdio::piped()).spawn().unwrap();
+        let started_at = Instant::now();
+
+        assert!(wait_with_timeout(child, Duration::from_millis(100)).is_none());
+        assert!(started_at.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn can_read_password_from_netrc() {
+        let contents = "machine other.host login a password wrong\nmachine github.com\n  login someone\n  password ghp_secret\ndefault login b password fallback\n";
+
+        assert_eq!(Some("ghp_secret".to_string()), parse_netrc(contents, "github.com"));
+        assert_eq!(None, parse_netrc(contents, "github.some-business.com"));
     }
 }
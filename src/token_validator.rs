@@ -0,0 +1,242 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use serde::Deserialize;
+use ureq::Response;
+
+use crate::{credential_store::CredentialStore, error::GittyError, http_agent::HttpProxyAgent, repository::GitRepository, token_retriever::{prompt_for_github_token_and_save, TokenSource}, user_input_generator::TextInputGeneratorTrait};
+
+/// Classic tokens need one of these to comment on commits, `public_repo` only being enough for public repositories.
+const COMMENT_SCOPES: [&str; 2] = ["repo", "public_repo"];
+const MAX_TOKEN_ATTEMPTS: usize = 3;
+
+#[derive(Deserialize)]
+struct AuthenticatedUser {
+    login: String,
+}
+
+/// What `/user` says about the token gitty is using.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TokenInfo {
+    login: String,
+    scopes: Option<Vec<String>>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl TokenInfo {
+    pub fn from_response(response: Response)-> Result<TokenInfo, GittyError> {
+        let scopes = response.header("X-OAuth-Scopes").map(parse_scopes);
+        let expires_at = response.header("github-authentication-token-expiration").and_then(parse_token_expiration);
+        let string_response = response.into_string()
+            .map_err(|error| GittyError::Network(error.to_string()))?;
+        let user = serde_json::from_str::<AuthenticatedUser>(&string_response)?;
+        Ok(TokenInfo{login: user.login, scopes, expires_at})
+    }
+
+    pub fn get_login(&self)-> &str {
+        &self.login
+    }
+
+    /// Fine-grained and app tokens do not report scopes, so only classic tokens can be found lacking.
+    pub fn is_missing_comment_scope(&self)-> bool {
+        match &self.scopes {
+            Some(scopes) => !scopes.iter().any(|scope| COMMENT_SCOPES.contains(&scope.as_str())),
+            None => false,
+        }
+    }
+
+    pub fn expires_within(&self, now: DateTime<Utc>, days: i64)-> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at - now <= Duration::days(days))
+    }
+
+    pub fn get_warnings(&self, now: DateTime<Utc>, warning_days: i64)-> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.is_missing_comment_scope() {
+            warnings.push(format!(
+                "token has scopes [{}] but needs repo (or public_repo for public repositories) to comment on commits",
+                self.scopes.as_deref().unwrap_or_default().join(", ")
+            ));
+        }
+        if let Some(expires_at) = self.expires_at.filter(|_| self.expires_within(now, warning_days)) {
+            if expires_at <= now {
+                warnings.push(format!("token expired on {}", expires_at.format("%Y-%m-%d")));
+            } else {
+                warnings.push(format!("token expires in {} days on {}", (expires_at - now).num_days(), expires_at.format("%Y-%m-%d")));
+            }
+        }
+        warnings
+    }
+}
+
+fn parse_scopes(header: &str)-> Vec<String> {
+    header.split(',')
+        .map(|scope| scope.trim().to_string())
+        .filter(|scope| !scope.is_empty())
+        .collect()
+}
+
+/// GitHub sends e.g. `2023-04-01 00:00:00 UTC`, older servers an offset such as `-0700` instead of `UTC`.
+fn parse_token_expiration(header: &str)-> Option<DateTime<Utc>> {
+    let header = header.trim();
+    match header.strip_suffix(" UTC") {
+        Some(timestamp) => NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S").ok().map(|timestamp| timestamp.and_utc()),
+        None => DateTime::parse_from_str(header, "%Y-%m-%d %H:%M:%S %z").ok().map(|timestamp| timestamp.with_timezone(&Utc)),
+    }
+}
+
+impl GitRepository {
+    pub fn get_token_info(&self, http_agent: &HttpProxyAgent)-> Result<TokenInfo, GittyError> {
+        let response = http_agent.get(&format!("{}/user", self.get_base_rest_url()))
+            .set("Authorization", &self.get_bearer_token_string()?)
+            .set("X-GitHub-Api-Version", "2022-11-28")
+            .call()?;
+        TokenInfo::from_response(response)
+    }
+}
+
+/// Checks the token before anything else uses it, asking for a new one while GitHub rejects it. A token from
+/// an environment variable is not replaced, since the variable would win over the saved one again next time.
+pub fn validate_token(
+    repository: &mut GitRepository,
+    token_source: &TokenSource,
+    http_agent: &HttpProxyAgent,
+    credential_store: &dyn CredentialStore,
+    user_input_generator: &mut dyn TextInputGeneratorTrait,
+    warning_days: i64,
+)-> Result<TokenInfo, GittyError> {
+    let mut attempts = 1;
+    let token_info = loop {
+        match repository.get_token_info(http_agent) {
+            Ok(token_info) => break token_info,
+            Err(GittyError::Auth(message)) if attempts == 1 && matches!(token_source, TokenSource::Environment(_)) => {
+                return Err(GittyError::Auth(format!("GitHub rejected the token in {}, fix or unset it: {}", token_source, message)))
+            },
+            Err(GittyError::Auth(message)) if attempts < MAX_TOKEN_ATTEMPTS => {
+                eprintln!("GitHub rejected the token: {}", message);
+                repository.set_token(prompt_for_github_token_and_save(credential_store, user_input_generator)?);
+                attempts += 1;
+            },
+            Err(error) => return Err(error),
+        }
+    };
+    println!("Authenticated to {} as {}", repository.get_host(), token_info.get_login());
+    token_info.get_warnings(Utc::now(), warning_days).iter()
+        .for_each(|warning| eprintln!("Warning: {}", warning));
+    Ok(token_info)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::{credential_store::{PlaintextCredentialStore, GITHUB_TOKEN_KEY}, http_agent::testing::serve_responses, profile::testing::temporary_profile, remote::RemoteLocation, user_input_generator::testing::MockTextInputGenerator};
+
+    fn user_response(headers: &str)-> Response {
+        format!("HTTP/1.1 200 OK\r\n{}\r\n{{\"login\": \"someone\", \"id\": 1}}", headers).parse().unwrap()
+    }
+
+    #[test]
+    fn can_read_token_info_from_user_response() {
+        let response = user_response("X-OAuth-Scopes: repo, read:org\r\ngithub-authentication-token-expiration: 2023-04-01 00:00:00 UTC\r\n");
+
+        let token_info = TokenInfo::from_response(response).unwrap();
+
+        assert_eq!("someone", token_info.get_login());
+        assert_eq!(Some(vec!["repo".to_string(), "read:org".to_string()]), token_info.scopes);
+        assert_eq!(Some(Utc.with_ymd_and_hms(2023, 4, 1, 0, 0, 0).unwrap()), token_info.expires_at);
+        assert!(!token_info.is_missing_comment_scope());
+    }
+
+    #[test]
+    fn token_without_repo_scope_is_flagged() {
+        let token_info = TokenInfo::from_response(user_response("X-OAuth-Scopes: read:org, gist\r\n")).unwrap();
+        assert!(token_info.is_missing_comment_scope());
+        assert_eq!(1, token_info.get_warnings(Utc::now(), 14).len());
+    }
+
+    #[test]
+    fn fine_grained_tokens_are_not_flagged_for_scopes() {
+        let token_info = TokenInfo::from_response(user_response("")).unwrap();
+        assert!(!token_info.is_missing_comment_scope());
+        assert!(token_info.get_warnings(Utc::now(), 14).is_empty());
+    }
+
+    #[test]
+    fn warns_when_token_expires_soon() {
+        let token_info = TokenInfo::from_response(user_response("X-OAuth-Scopes: repo\r\ngithub-authentication-token-expiration: 2023-04-10 00:00:00 UTC\r\n")).unwrap();
+
+        assert!(token_info.get_warnings(Utc.with_ymd_and_hms(2023, 3, 1, 0, 0, 0).unwrap(), 14).is_empty());
+        assert_eq!(
+            vec!["token expires in 5 days on 2023-04-10".to_string()],
+            token_info.get_warnings(Utc.with_ymd_and_hms(2023, 4, 5, 0, 0, 0).unwrap(), 14)
+        );
+        assert_eq!(
+            vec!["token expired on 2023-04-10".to_string()],
+            token_info.get_warnings(Utc.with_ymd_and_hms(2023, 5, 1, 0, 0, 0).unwrap(), 14)
+        );
+    }
+
+    #[test]
+    fn rejected_token_triggers_a_new_prompt() {
+        let (base_url, server) = serve_responses(vec![
+            "HTTP/1.1 401 Unauthorized\r\n\r\n{\"message\":\"Bad credentials\"}".to_string(),
+            "HTTP/1.1 200 OK\r\nX-OAuth-Scopes: repo\r\n\r\n{\"login\": \"someone\"}".to_string(),
+        ]);
+        let profile = temporary_profile("rejected_token_triggers_a_new_prompt");
+        let credential_store = PlaintextCredentialStore::new(profile.get_dir().to_path_buf());
+        let mut repository = GitRepository::new("stale".to_string(), RemoteLocation::parse(&format!("{}/bfrazho/gitty", base_url)).unwrap(), "main".to_string());
+        let mut user_input_generator = MockTextInputGenerator::new(vec!["fresh".to_string()]);
+
+        let token_info = validate_token(&mut repository, &TokenSource::Netrc("/home/someone/.netrc".into()), &HttpProxyAgent::new_with_proxy(""), &credential_store, &mut user_input_generator, 14).unwrap();
+
+        assert_eq!("someone", token_info.get_login());
+        assert_eq!("fresh", repository.get_token().unwrap());
+        assert_eq!(Some("fresh".to_string()), credential_store.get(GITHUB_TOKEN_KEY).unwrap());
+        let requests = server.join().unwrap();
+        assert!(requests[0].to_lowercase().contains("authorization: bearer stale"));
+        assert!(requests[1].to_lowercase().contains("authorization: bearer fresh"));
+    }
+
+    #[test]
+    fn missing_repo_scope_is_reported() {
+        let (base_url, server) = serve_responses(vec![
+            "HTTP/1.1 200 OK\r\nX-OAuth-Scopes: read:org, gist\r\n\r\n{\"login\": \"someone\"}".to_string(),
+        ]);
+        let profile = temporary_profile("missing_repo_scope_is_reported");
+        let credential_store = PlaintextCredentialStore::new(profile.get_dir().to_path_buf());
+        let mut repository = GitRepository::new("token".to_string(), RemoteLocation::parse(&format!("{}/bfrazho/gitty", base_url)).unwrap(), "main".to_string());
+
+        let token_info = validate_token(&mut repository, &TokenSource::Prompt, &HttpProxyAgent::new_with_proxy(""), &credential_store, &mut MockTextInputGenerator::new(Vec::new()), 14).unwrap();
+
+        assert!(token_info.is_missing_comment_scope());
+        assert_eq!(
+            vec!["token has scopes [read:org, gist] but needs repo (or public_repo for public repositories) to comment on commits".to_string()],
+            token_info.get_warnings(Utc::now(), 14)
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn rejected_environment_token_is_reported_not_replaced() {
+        let (base_url, server) = serve_responses(vec!["HTTP/1.1 401 Unauthorized\r\n\r\n{\"message\":\"Bad credentials\"}".to_string()]);
+        let profile = temporary_profile("rejected_environment_token_is_reported_not_replaced");
+        let credential_store = PlaintextCredentialStore::new(profile.get_dir().to_path_buf());
+        let mut repository = GitRepository::new("stale".to_string(), RemoteLocation::parse(&format!("{}/bfrazho/gitty", base_url)).unwrap(), "main".to_string());
+        let mut user_input_generator = MockTextInputGenerator::new(vec!["fresh".to_string()]);
+
+        let result = validate_token(&mut repository, &TokenSource::Environment("GITHUB_TOKEN".to_string()), &HttpProxyAgent::new_with_proxy(""), &credential_store, &mut user_input_generator, 14);
+
+        assert!(matches!(&result, Err(GittyError::Auth(message)) if message.contains("GITHUB_TOKEN")), "{:?}", result);
+        assert_eq!(None, credential_store.get(GITHUB_TOKEN_KEY).unwrap());
+        assert_eq!(Some("fresh".to_string()), user_input_generator.get_password_input(""));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn can_parse_token_expiration_with_offset() {
+        assert_eq!(
+            Some(Utc.with_ymd_and_hms(2023, 4, 1, 7, 0, 0).unwrap()),
+            parse_token_expiration("2023-04-01 00:00:00 -0700")
+        );
+        assert_eq!(None, parse_token_expiration("soon"));
+    }
+}
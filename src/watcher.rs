@@ -0,0 +1,307 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use chrono::{DateTime, Local};
+
+use crate::{collaborator::Collaborator, commit::Commit, error::GittyError, http_agent::HttpProxyAgent, repository::GitRepository, state::{BranchState, WatcherState}};
+
+/// A commit that has not been seen before and the watched branch it was found on.
+#[derive(PartialEq, Eq, Debug)]
+pub struct DiscoveredCommit {
+    branch: String,
+    commit: Commit,
+}
+
+impl DiscoveredCommit {
+    pub fn get_branch(&self)-> &str {
+        &self.branch
+    }
+    pub fn get_commit(&self)-> &Commit {
+        &self.commit
+    }
+}
+
+/// What one poll of every watched branch turned up.
+#[derive(PartialEq, Eq, Debug, Default)]
+pub struct Discovery {
+    commits: Vec<DiscoveredCommit>,
+    heads: BTreeMap<String, String>,
+}
+
+impl Discovery {
+    pub fn get_commits(&self)-> &[DiscoveredCommit] {
+        &self.commits
+    }
+    /// The newest commit seen on each branch that had any.
+    pub fn get_heads(&self)-> &BTreeMap<String, String> {
+        &self.heads
+    }
+}
+
+/// New commits on `branch` since the last poll, newest first, and the branch head to resume from next time.
+/// A branch seen for the first time only has its head recorded, so every later poll walks back to a known commit.
+fn poll_branch(repository: &GitRepository, http_agent: &HttpProxyAgent, branch: &str, branch_state: Option<&BranchState>, polled_at: DateTime<Local>)-> Result<(Vec<Commit>, Option<String>), GittyError> {
+    let commits = match (branch_state.and_then(BranchState::get_last_seen_oid), branch_state.and_then(BranchState::get_last_polled_at)) {
+        (Some(last_seen_oid), _) => match repository.get_branch_commits_since_oid(http_agent, branch, last_seen_oid)? {
+            Some(commits) => commits,
+            None => {
+                eprintln!("{} is no longer on {}, it may have been force-pushed. Watching from its head again without approving older commits", last_seen_oid, branch);
+                return Ok((Vec::new(), repository.get_branch_head_oid(http_agent, branch)?))
+            },
+        },
+        // Polled before commits were remembered by OID: catch up by date once.
+        (None, Some(_)) => repository.get_branch_commits_since_timestamp(http_agent, branch, branch_state.map_or(polled_at, |branch_state| branch_state.get_resume_timestamp(polled_at)))?,
+        (None, None) => Vec::new(),
+    };
+    let head = match commits.first() {
+        Some(head) => Some(head.get_id().to_string()),
+        None if branch_state.and_then(BranchState::get_last_seen_oid).is_none() => repository.get_branch_head_oid(http_agent, branch)?,
+        None => None,
+    };
+    Ok((commits, head))
+}
+
+/// Reads each of `branches` from where the last poll of it stopped. A commit on several branches is
+/// reported once, for the first of `branches` it is on. Branches deleted since they were listed are skipped.
+pub fn discover_new_commits(repository: &GitRepository, http_agent: &HttpProxyAgent, branches: &[String], state: &WatcherState, polled_at: DateTime<Local>)-> Result<Discovery, GittyError> {
+    let mut discovery = Discovery::default();
+    for branch in branches {
+        let branch_state = state.get_branch_state(&repository.get_branch_state_key(branch));
+        let (commits, head) = match poll_branch(repository, http_agent, branch, branch_state, polled_at) {
+            Ok(polled) => polled,
+            Err(GittyError::NotFound(message)) if branch != repository.get_main_branch_name() => {
+                eprintln!("Skipping branch {}: {}", branch, message);
+                continue;
+            },
+            Err(error) => return Err(error),
+        };
+        if let Some(head) = head {
+            discovery.heads.insert(branch.clone(), head);
+        }
+        for commit in commits {
+            if discovery.commits.iter().all(|discovered| discovered.commit.get_id() != commit.get_id()) {
+                discovery.commits.push(DiscoveredCommit{branch: branch.clone(), commit});
+            }
+        }
+    }
+    Ok(discovery)
+}
+
+pub fn approve_new_commits(repository: &GitRepository, http_agent: &HttpProxyAgent, branches: &[String], collaborators: &[Collaborator], state: &mut WatcherState)-> Result<(), GittyError> {
+    let polled_at = Local::now();
+    let discovery = discover_new_commits(repository, http_agent, branches, state, polled_at)?;
+    let state_keys: Vec<String> = branches.iter().map(|branch| repository.get_branch_state_key(branch)).collect();
+
+    for discovered in discovery.get_commits().iter().filter(|discovered| discovered.get_commit().is_authored_by_any_of(collaborators)) {
+        let commit = discovered.get_commit();
+        if state_keys.iter().any(|state_key| state.get_branch_state(state_key).is_some_and(|branch_state| branch_state.is_approved(commit.get_id()))) {
+            continue;
+        }
+        println!("commit on {}: {:?}", discovered.get_branch(), commit);
+        repository.post_comment_on_commit_that_you_approve_it(http_agent, commit)?;
+        state.get_branch_state_mut(&repository.get_branch_state_key(discovered.get_branch())).mark_approved(commit.get_id());
+        state.save()?;
+    }
+
+    for branch in branches {
+        let head = discovery.get_heads().get(branch).cloned();
+        state.get_branch_state_mut(&repository.get_branch_state_key(branch)).record_poll(polled_at, head);
+    }
+    state.save()
+}
+
+/// Runs once at startup so anything pushed while gitty was not running still gets approved.
+pub fn reconcile_missed_commits(repository: &GitRepository, http_agent: &HttpProxyAgent, branches: &[String], collaborators: &[Collaborator], state: &mut WatcherState)-> Result<(), GittyError> {
+    for branch in branches {
+        if let Some(branch_state) = state.get_branch_state(&repository.get_branch_state_key(branch)) {
+            match (branch_state.get_last_seen_oid(), branch_state.get_last_polled_at()) {
+                (Some(last_seen_oid), _) => println!("Reconciling commits pushed to {} after {}", branch, last_seen_oid),
+                (None, Some(last_polled_at)) => println!("Reconciling commits pushed to {} since {}", branch, last_polled_at.to_rfc3339()),
+                (None, None) => {},
+            }
+        }
+    }
+    approve_new_commits(repository, http_agent, branches, collaborators, state)
+}
+
+/// Doubles the wait after each consecutive failure, up to `max_delay`.
+pub struct Backoff {
+    interval: Duration,
+    max_delay: Duration,
+    consecutive_failures: u32,
+}
+
+impl Backoff {
+    pub fn new(interval: Duration, max_delay: Duration)-> Self {
+        Self{interval, max_delay, consecutive_failures: 0}
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+    }
+
+    pub fn record_failure(&mut self) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+    }
+
+    pub fn next_delay(&self)-> Duration {
+        self.interval
+            .saturating_mul(2u32.saturating_pow(self.consecutive_failures))
+            .min(self.max_delay)
+    }
+}
+
+/// Keeps calling `action` until it succeeds or fails with an error that waiting will not fix.
+pub fn retry_transient_errors<T>(backoff: &mut Backoff, mut action: impl FnMut()-> Result<T, GittyError>)-> Result<T, GittyError> {
+    loop {
+        match action() {
+            Ok(result) => {
+                backoff.record_success();
+                return Ok(result)
+            },
+            Err(error) if error.is_transient() => {
+                backoff.record_failure();
+                println!("{}, retrying in {}s", error, backoff.next_delay().as_secs());
+                std::thread::sleep(backoff.next_delay());
+            },
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{commit::testing::history_response, http_agent::testing::serve_responses, remote::RemoteLocation};
+
+    fn serve_repository(responses: Vec<String>)-> (GitRepository, HttpProxyAgent, std::thread::JoinHandle<Vec<String>>) {
+        let (base_url, server) = serve_responses(responses);
+        let repository = GitRepository::new("token".to_string(), RemoteLocation::parse(&format!("{}/bfrazho/gitty", base_url)).unwrap(), "main".to_string());
+        (repository, HttpProxyAgent::new_with_proxy(""), server)
+    }
+
+    fn record_heads(repository: &GitRepository, discovery: &Discovery, state: &mut WatcherState) {
+        for (branch, head) in discovery.get_heads() {
+            state.get_branch_state_mut(&repository.get_branch_state_key(branch)).record_poll(Local::now(), Some(head.clone()));
+        }
+    }
+
+    #[test]
+    fn discovery_tags_commits_with_the_first_branch_they_are_on() {
+        let (repository, http_agent, server) = serve_repository(vec![
+            history_response(&["c2", "c1", "c0"]),
+            history_response(&["c3", "c1", "c0"]),
+        ]);
+        let branches = vec!["main".to_string(), "release/1.2".to_string()];
+        let mut state = WatcherState::default();
+        for branch in branches.iter() {
+            state.get_branch_state_mut(&repository.get_branch_state_key(branch)).record_poll(Local::now(), Some("c0".to_string()));
+        }
+
+        let discovery = discover_new_commits(&repository, &http_agent, &branches, &state, Local::now()).unwrap();
+
+        assert_eq!(
+            vec![("main", "c2"), ("main", "c1"), ("release/1.2", "c3")],
+            discovery.get_commits().iter().map(|discovered| (discovered.get_branch(), discovered.get_commit().get_id())).collect::<Vec<_>>()
+        );
+        assert_eq!(Some(&"c3".to_string()), discovery.get_heads().get("release/1.2"));
+        let requests = server.join().unwrap();
+        assert!(requests[1].contains("object(expression: \\\"release/1.2\\\")"));
+    }
+
+    #[test]
+    fn first_poll_records_the_head_so_the_next_walks_by_oid() {
+        let (repository, http_agent, server) = serve_repository(vec![
+            history_response(&["c1"]),
+            history_response(&["c3", "c2", "c1", "c0"]),
+        ]);
+        let branches = vec!["main".to_string()];
+        let mut state = WatcherState::default();
+
+        let first = discover_new_commits(&repository, &http_agent, &branches, &state, Local::now()).unwrap();
+        assert!(first.get_commits().is_empty());
+        assert_eq!(Some(&"c1".to_string()), first.get_heads().get("main"));
+        record_heads(&repository, &first, &mut state);
+
+        let second = discover_new_commits(&repository, &http_agent, &branches, &state, Local::now()).unwrap();
+        assert_eq!(vec!["c3", "c2"], second.get_commits().iter().map(|discovered| discovered.get_commit().get_id()).collect::<Vec<&str>>());
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains("history(first: 1)"));
+        assert!(requests[1].contains("history(first: 100)"));
+        assert!(!requests[1].contains("since"));
+    }
+
+    #[test]
+    fn deleted_branches_are_skipped() {
+        let (repository, http_agent, server) = serve_repository(vec![
+            history_response(&["c2", "c1"]),
+            "HTTP/1.1 200 OK\r\n\r\n{\"data\":{\"repository\":{\"object\":null}}}".to_string(),
+        ]);
+        let branches = vec!["main".to_string(), "release/1.2".to_string()];
+        let mut state = WatcherState::default();
+        for branch in branches.iter() {
+            state.get_branch_state_mut(&repository.get_branch_state_key(branch)).record_poll(Local::now(), Some("c1".to_string()));
+        }
+
+        let discovery = discover_new_commits(&repository, &http_agent, &branches, &state, Local::now()).unwrap();
+
+        assert_eq!(vec!["c2"], discovery.get_commits().iter().map(|discovered| discovered.get_commit().get_id()).collect::<Vec<&str>>());
+        assert_eq!(None, discovery.get_heads().get("release/1.2"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn force_pushed_branch_is_watched_from_its_new_head() {
+        let (repository, http_agent, server) = serve_repository(vec![
+            history_response(&["x2", "x1"]),
+            history_response(&["x2"]),
+        ]);
+        let mut state = WatcherState::default();
+        state.get_branch_state_mut(&repository.get_state_key()).record_poll(Local::now(), Some("gone".to_string()));
+
+        let discovery = discover_new_commits(&repository, &http_agent, &["main".to_string()], &state, Local::now()).unwrap();
+
+        assert!(discovery.get_commits().is_empty());
+        assert_eq!(Some(&"x2".to_string()), discovery.get_heads().get("main"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn backoff_doubles_until_max_delay() {
+        let mut backoff = Backoff::new(Duration::from_secs(10), Duration::from_secs(60));
+        assert_eq!(Duration::from_secs(10), backoff.next_delay());
+        backoff.record_failure();
+        assert_eq!(Duration::from_secs(20), backoff.next_delay());
+        backoff.record_failure();
+        assert_eq!(Duration::from_secs(40), backoff.next_delay());
+        backoff.record_failure();
+        assert_eq!(Duration::from_secs(60), backoff.next_delay());
+        backoff.record_success();
+        assert_eq!(Duration::from_secs(10), backoff.next_delay());
+    }
+
+    #[test]
+    fn retry_stops_on_permanent_errors() {
+        let mut backoff = Backoff::new(Duration::from_millis(1), Duration::from_millis(1));
+        let mut attempts = 0;
+        let result: Result<(), GittyError> = retry_transient_errors(&mut backoff, || {
+            attempts += 1;
+            Err(GittyError::Auth("bad token".to_string()))
+        });
+        assert_eq!(Err(GittyError::Auth("bad token".to_string())), result);
+        assert_eq!(1, attempts);
+    }
+
+    #[test]
+    fn retry_recovers_from_transient_errors() {
+        let mut backoff = Backoff::new(Duration::from_millis(1), Duration::from_millis(1));
+        let mut attempts = 0;
+        let result = retry_transient_errors(&mut backoff, || {
+            attempts += 1;
+            if attempts < 3 { Err(GittyError::Network("502".to_string())) } else { Ok(attempts) }
+        });
+        assert_eq!(Ok(3), result);
+    }
+}
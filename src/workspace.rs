@@ -0,0 +1,133 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use crate::{error::GittyError, remote::RemoteLocation, repository::get_remote_location};
+
+/// One repository watched in workspace mode, and the local checkout it was found in, if any.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct WorkspaceMember {
+    remote: RemoteLocation,
+    checkout: Option<PathBuf>,
+}
+
+impl WorkspaceMember {
+    pub fn get_remote(&self)-> &RemoteLocation {
+        &self.remote
+    }
+    pub fn get_checkout(&self)-> Option<&Path> {
+        self.checkout.as_deref()
+    }
+}
+
+/// `owner/repo` is taken to be on `default_host`. `host/owner/repo` and anything git accepts as a remote url also work.
+pub fn parse_workspace_repository(entry: &str, default_host: &str)-> Result<RemoteLocation, GittyError> {
+    let entry = entry.trim();
+    if let Ok(remote) = RemoteLocation::parse(entry) {
+        return Ok(remote)
+    }
+    match entry.trim_matches('/').split('/').filter(|segment| !segment.is_empty()).count() {
+        0 | 1 => Err(GittyError::Config(format!("workspace repository \"{}\" should look like owner/repo", entry))),
+        2 => RemoteLocation::parse(&format!("https://{}/{}", default_host, entry)),
+        _ => RemoteLocation::parse(&format!("https://{}", entry)),
+    }
+}
+
+/// Every git checkout directly inside `dir`, by where its `remote_name` (or default remote) points.
+/// Checkouts without a GitHub remote are skipped with a warning.
+pub fn find_checkouts(dir: &Path, remote_name: Option<&str>)-> Result<Vec<WorkspaceMember>, GittyError> {
+    let entries = fs::read_dir(dir)
+        .map_err(|error| GittyError::Config(format!("failed to read workspace directory {}: {}", dir.display(), error)))?;
+    let mut checkouts: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.join(".git").exists())
+        .collect();
+    checkouts.sort();
+    Ok(checkouts.into_iter()
+        .filter_map(|checkout| match get_remote_location(&checkout, remote_name) {
+            Ok(remote) => Some(WorkspaceMember{remote, checkout: Some(checkout)}),
+            Err(error) => {
+                eprintln!("Skipping {}: {}", checkout.display(), error);
+                None
+            },
+        })
+        .collect())
+}
+
+/// The listed repositories followed by the checkouts in `dir`, each repository once. They share one token,
+/// so they must all be on the same host.
+pub fn resolve_workspace(repositories: &[String], dir: Option<&Path>, remote_name: Option<&str>, default_host: &str)-> Result<Vec<WorkspaceMember>, GittyError> {
+    let mut members = repositories.iter()
+        .map(|entry| parse_workspace_repository(entry, default_host).map(|remote| WorkspaceMember{remote, checkout: None}))
+        .collect::<Result<Vec<WorkspaceMember>, GittyError>>()?;
+    if let Some(dir) = dir {
+        members.extend(find_checkouts(dir, remote_name)?);
+    }
+    let mut unique: Vec<WorkspaceMember> = Vec::new();
+    for member in members {
+        match unique.iter_mut().find(|existing| existing.remote.to_string().eq_ignore_ascii_case(&member.remote.to_string())) {
+            Some(existing) => existing.checkout = existing.checkout.take().or(member.checkout),
+            None => unique.push(member),
+        }
+    }
+    if let Some(first) = unique.first() {
+        if let Some(other) = unique.iter().find(|member| member.remote.get_host() != first.remote.get_host()) {
+            return Err(GittyError::Config(format!("workspace repositories must all be on one host, found {} and {}", first.remote, other.remote)))
+        }
+    }
+    if unique.is_empty() {
+        return Err(GittyError::Config("no workspace repositories, list owner/repo names or pass --dir".to_string()))
+    }
+    Ok(unique)
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn can_parse_workspace_repositories() {
+        let cases = vec![
+            ("bfrazho/gitty", "github.com/bfrazho/gitty"),
+            ("github.some-business.com/team/gitty", "github.some-business.com/team/gitty"),
+            ("git@github.com:bfrazho/gitty.git", "github.com/bfrazho/gitty"),
+            ("https://github.com/bfrazho/gitty/", "github.com/bfrazho/gitty"),
+        ];
+        for (entry, expected) in cases {
+            assert_eq!(expected, parse_workspace_repository(entry, "github.com").unwrap().to_string(), "{}", entry);
+        }
+        assert!(parse_workspace_repository("gitty", "github.com").is_err());
+    }
+
+    #[test]
+    fn finds_checkouts_with_github_remotes() {
+        let dir = env::temp_dir().join("gitty_finds_checkouts_with_github_remotes");
+        fs::remove_dir_all(&dir).unwrap_or_default();
+        for (name, remote) in [("api", Some("git@github.com:team/api.git")), ("scratch", None), ("web", Some("https://github.com/team/web"))] {
+            gix::init(dir.join(name)).unwrap();
+            if let Some(remote) = remote {
+                let config_path = dir.join(name).join(".git/config");
+                let config = fs::read_to_string(&config_path).unwrap();
+                fs::write(&config_path, format!("{}[remote \"origin\"]\n\turl = {}\n\tfetch = +refs/heads/*:refs/remotes/origin/*\n", config, remote)).unwrap();
+            }
+        }
+        fs::write(dir.join("notes.txt"), "not a checkout").unwrap();
+
+        let members = resolve_workspace(&["team/web".to_string(), "team/docs".to_string()], Some(&dir), None, "github.com").unwrap();
+
+        assert_eq!(
+            vec!["github.com/team/web", "github.com/team/docs", "github.com/team/api"],
+            members.iter().map(|member| member.get_remote().to_string()).collect::<Vec<String>>()
+        );
+        assert_eq!(Some(dir.join("web").as_path()), members[0].get_checkout());
+        assert_eq!(None, members[1].get_checkout());
+        assert_eq!(Some(dir.join("api").as_path()), members[2].get_checkout());
+    }
+
+    #[test]
+    fn workspace_must_be_on_one_host() {
+        let result = resolve_workspace(&["team/api".to_string(), "github.some-business.com/team/web".to_string()], None, None, "github.com");
+        assert!(matches!(result, Err(GittyError::Config(_))));
+        assert!(matches!(resolve_workspace(&[], None, None, "github.com"), Err(GittyError::Config(_))));
+    }
+}